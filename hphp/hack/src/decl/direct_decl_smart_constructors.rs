@@ -3,7 +3,10 @@
 // This source code is licensed under the MIT license found in the
 // LICENSE file in the "hack" directory of this source tree.
 
+use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
 use bstr::BStr;
@@ -43,9 +46,9 @@ use oxidized_by_ref::{
     typing_defs::{
         self, AbstractTypeconst, Capability::*, ClassConstKind, ConcreteTypeconst, ConstDecl,
         Enforcement, EnumType, FunArity, FunElt, FunImplicitParams, FunParam, FunParams, FunType,
-        IfcFunDecl, ParamMode, PosByteString, PosId, PosString, PossiblyEnforcedTy, RecordFieldReq,
-        ShapeFieldType, ShapeKind, TaccessType, Tparam, TshapeFieldName, Ty, Ty_, Typeconst,
-        TypedefType, WhereConstraint, XhpAttrTag,
+        IfcFunDecl, ModuleDefType, ParamMode, PosByteString, PosId, PosString, PossiblyEnforcedTy,
+        RecordFieldReq, ShapeFieldType, ShapeKind, TaccessType, Tparam, TshapeFieldName, Ty, Ty_,
+        Typeconst, TypedefType, WhereConstraint, XhpAttrTag,
     },
     typing_defs_flags::{FunParamFlags, FunTypeFlags},
     typing_modules::Module_,
@@ -62,6 +65,17 @@ type SK = SyntaxKind;
 
 type SSet<'a> = arena_collections::SortedSet<'a, &'a str>;
 
+/// The value a constant expression folds to, used by `eval_const_expr`.
+/// Deliberately lifetime-free (an owned `String` rather than an arena slice)
+/// so recursive folding doesn't need to thread an allocator through every
+/// intermediate step; see `DirectDeclSmartConstructors::try_fold_const_expr`.
+enum ConstFoldValue {
+    Int(i64),
+    Float(f64),
+    Str(std::string::String),
+    Bool(bool),
+}
+
 #[derive(Clone)]
 pub struct DirectDeclSmartConstructors<'a, 'text, S: SourceTextAllocator<'text, 'a>> {
     pub token_factory: SimpleTokenFactoryImpl<CompactToken>,
@@ -77,6 +91,24 @@ pub struct DirectDeclSmartConstructors<'a, 'text, S: SourceTextAllocator<'text,
     namespace_builder: Rc<NamespaceBuilder<'a>>,
     classish_name_builder: ClassishNameBuilder<'a>,
     type_parameters: Rc<Vec<'a, SSet<'a>>>,
+    // Set by a top-level `module Foo.Bar;` membership statement. A file may
+    // declare at most one membership; every decl produced afterward in this
+    // file falls back to it when it doesn't carry its own `__Module`
+    // attribute (see `resolve_module`).
+    file_module: Option<&'a Module_<'a>>,
+
+    // Caches type-argument slices by a hash of their rendered content, so
+    // structurally-identical `targs` (overwhelmingly empty, or a repeated
+    // singleton like `Tgeneric("T")`) collapse to one arena allocation
+    // instead of a fresh one per occurrence. See `intern_targs`.
+    targs_interner: RefCell<HashMap<u64, std::vec::Vec<(std::string::String, &'a [&'a Ty<'a>])>>>,
+
+    // Caches whole `Ty_` payloads the same way `targs_interner` caches
+    // argument lists, keyed on `render_ty_`'s content-only fingerprint (which
+    // excludes `Reason`). See `intern_ty_ctor`. Only consulted when
+    // `opts.intern_ty_constructors` is set, since it's pure overhead for
+    // callers that don't care about the extra sharing.
+    ty_ctor_interner: RefCell<HashMap<u64, std::vec::Vec<(std::string::String, &'a Ty_<'a>)>>>,
 
     previous_token_kind: TokenKind,
 
@@ -113,6 +145,9 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
             )),
             classish_name_builder: ClassishNameBuilder::new(),
             type_parameters: Rc::new(Vec::new_in(arena)),
+            file_module: None,
+            targs_interner: RefCell::new(HashMap::new()),
+            ty_ctor_interner: RefCell::new(HashMap::new()),
             // EndOfFile is used here as a None value (signifying "beginning of
             // file") to save space. There is no legitimate circumstance where
             // we would parse a token and the previous token kind would be
@@ -127,6 +162,123 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
         self.arena.alloc(val)
     }
 
+    /// Builds a `Ty` for `ty_`, routing the position-free sentinel kinds
+    /// (`Terr`, `Tnonnull`, `Tdynamic`, the empty `Tunion`) through the
+    /// static, pre-allocated `TERR`/`TNONNULL`/`TDYNAMIC`/`TUNION_EMPTY`
+    /// constants instead of allocating a new one
+    /// into the arena every time one of these is produced. `node_to_ty_`
+    /// should go through this rather than calling `self.alloc(Ty(..))`
+    /// directly whenever the `Ty_` being built might be one of these kinds.
+    fn intern_ty(&self, reason: &'a Reason<'a>, ty_: Ty_<'a>) -> &'a Ty<'a> {
+        match ty_ {
+            Ty_::Terr => TERR,
+            Ty_::Tnonnull => TNONNULL,
+            Ty_::Tdynamic => TDYNAMIC,
+            Ty_::Tunion(&[]) => TUNION_EMPTY,
+            ty_ if self.opts.intern_ty_constructors => {
+                self.alloc(Ty(reason, *self.intern_ty_ctor(ty_)))
+            }
+            ty_ => self.alloc(Ty(reason, ty_)),
+        }
+    }
+
+    /// Dedupes a `Ty_` payload against previously-seen ones with the same
+    /// structural content, the same way `intern_targs` dedupes argument
+    /// lists: fingerprint via `render_ty_` (which, like `Pos`-blind
+    /// `targs_fingerprint`, ignores positions, so two argument-less
+    /// `Tapply`s that only differ in the position of their name still share
+    /// one allocation), then cache by a hash of that fingerprint.
+    /// `render_ty_` falls back to the literal string `"other"` for shapes it
+    /// doesn't special-case, so (as with `intern_targs`) a payload whose
+    /// fingerprint contains it is never deduped -- conflating two different
+    /// `"other"` payloads would be worse than the allocation this saves.
+    ///
+    /// Payloads that embed nested `Ty`s (a `Tapply`/`Tgeneric` with type
+    /// arguments, a `TvecOrDict`) are never deduped either, since each
+    /// nested `Ty` carries its own position-sensitive `Reason`. Only `Ty_`
+    /// itself is shared here; the caller's `Reason` always stays a fresh,
+    /// per-occurrence wrapper (see `intern_ty`).
+    fn intern_ty_ctor(&self, ty_: Ty_<'a>) -> &'a Ty_<'a> {
+        let has_nested_ty = match ty_ {
+            Ty_::Tapply(&(_, targs)) | Ty_::Tgeneric(&(_, targs)) => !targs.is_empty(),
+            Ty_::TvecOrDict(_) => true,
+            _ => false,
+        };
+        let fingerprint = self.render_ty_(&ty_);
+        if has_nested_ty || fingerprint.contains("other") {
+            return self.alloc(ty_);
+        }
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        fingerprint.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let mut interner = self.ty_ctor_interner.borrow_mut();
+        let bucket = interner.entry(key).or_insert_with(std::vec::Vec::new);
+        if let Some((_, existing)) = bucket.iter().find(|(fp, _)| *fp == fingerprint) {
+            return existing;
+        }
+        let interned = self.alloc(ty_);
+        bucket.push((fingerprint, interned));
+        interned
+    }
+
+    /// Fast-path equality for two `Ty_`s that may have come out of
+    /// `intern_ty_ctor`: identical pointers imply structural equality
+    /// without re-rendering either side. Exposed for later passes (outside
+    /// this constructor) that hold onto interned `Ty_`s; callers still need
+    /// a full structural comparison as a fallback for payloads that weren't
+    /// interned (e.g. `opts.intern_ty_constructors` is off, or the payload
+    /// fingerprinted as `"other"`).
+    pub fn ty_ctor_ptr_eq(a: &'a Ty_<'a>, b: &'a Ty_<'a>) -> bool {
+        std::ptr::eq(a, b)
+    }
+
+    /// Dedupes a type-argument list against previously-seen lists with the
+    /// same content, so the thousands of structurally-identical `targs`
+    /// that `convert_tapply_to_tgeneric` and friends rebuild (overwhelmingly
+    /// empty, or a repeated singleton like `Tgeneric("T")`'s `&[]`) collapse
+    /// to one arena allocation apiece, the way rustc interns `Substs`.
+    ///
+    /// Content is compared via `render_ty`, which already ignores `Pos`/
+    /// `Reason` (those must stay per-occurrence; only the slice backing
+    /// them is shared). `render_ty` isn't a lossless encoding of every
+    /// `Ty_` shape -- it falls back to the literal string `"other"` for
+    /// variants it doesn't special-case -- so any list containing one of
+    /// those is never deduped: that would risk conflating two different
+    /// `"other"` types into one cached slice, which is worse than the
+    /// allocation this is meant to save.
+    fn intern_targs(&self, items: &[&'a Ty<'a>]) -> &'a [&'a Ty<'a>] {
+        if items.is_empty() {
+            return EMPTY_TARGS;
+        }
+        let fingerprint = self.targs_fingerprint(items);
+        if fingerprint.contains("other") {
+            return self.slice(items.iter().copied());
+        }
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        fingerprint.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let mut interner = self.targs_interner.borrow_mut();
+        let bucket = interner.entry(key).or_insert_with(std::vec::Vec::new);
+        if let Some((_, existing)) = bucket.iter().find(|(fp, _)| *fp == fingerprint) {
+            return existing;
+        }
+        let interned = self.slice(items.iter().copied());
+        bucket.push((fingerprint, interned));
+        interned
+    }
+
+    fn targs_fingerprint(&self, items: &[&'a Ty<'a>]) -> std::string::String {
+        let mut fingerprint = std::string::String::new();
+        for item in items {
+            fingerprint.push_str(&self.render_ty(item));
+            fingerprint.push(';');
+        }
+        fingerprint
+    }
+
     fn qualified_name_from_parts(&self, parts: &'a [Node<'a>], pos: &'a Pos<'a>) -> Id<'a> {
         // Count the length of the qualified name, so that we can allocate
         // exactly the right amount of space for it in our arena.
@@ -174,6 +326,22 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
         Id(pos, qualified_name.into_bump_str())
     }
 
+    /// Splits a dotted module name like `"Foo.Bar"` into the `Module_(head,
+    /// rest)` shape used both for a symbol's own `__Module` attribute and for
+    /// a file's top-level `module Foo.Bar;` membership statement.
+    fn parse_dotted_module_name(&self, name: &str) -> Option<&'a Module_<'a>> {
+        let mut parts = name.split('.');
+        let head = parts.next()?;
+        let rest = parts.collect::<std::vec::Vec<_>>();
+        Some(self.alloc(Module_(head, self.alloc(rest))))
+    }
+
+    /// A decl's module is its own `__Module` attribute if present, else the
+    /// module the enclosing file declared membership in (if any).
+    fn resolve_module(&self, attr_module: Option<&'a Module_<'a>>) -> Option<&'a Module_<'a>> {
+        attr_module.or(self.file_module)
+    }
+
     /// If the given node is an identifier, XHP name, or qualified name,
     /// elaborate it in the current namespace and return Some. To be used for
     /// the name of a decl in its definition (e.g., "C" in `class C {}` or "f"
@@ -349,10 +517,166 @@ fn tany() -> &'static Ty<'static> {
     TANY
 }
 
+// Like `TANY` above: these carry no useful position (there's nothing a
+// `Reason::hint(pos)` on `nothing`/`nonnull`/`dynamic`/`_` would add that the
+// enclosing `Ty`'s own reason doesn't already cover), so rather than
+// reallocate one per occurrence, each is allocated once for the whole
+// program and handed out by `DirectDeclSmartConstructors::intern_ty`.
+const TERR: &Ty<'_> = &Ty(Reason::none(), Ty_::Terr);
+const TNONNULL: &Ty<'_> = &Ty(Reason::none(), Ty_::Tnonnull);
+const TDYNAMIC: &Ty<'_> = &Ty(Reason::none(), Ty_::Tdynamic);
+const TUNION_EMPTY: &Ty<'_> = &Ty(Reason::none(), Ty_::Tunion(&[]));
+
+// The empty type-argument list is by far the most common one `intern_targs`
+// sees (most `Tapply`/`Tgeneric` references have no generic args at all).
+const EMPTY_TARGS: &[&Ty<'_>] = &[];
+
 fn default_ifc_fun_decl<'a>() -> IfcFunDecl<'a> {
     IfcFunDecl::FDPolicied(Some("PUBLIC"))
 }
 
+/// Companion reader for `DirectDeclSmartConstructors::dump_decls`. See that
+/// method's doc comment for exactly which decl kinds round-trip; everything
+/// else is recognized (so it doesn't trip up parsing later lines) but
+/// produces no decl. The position recorded in a dumped line can't be
+/// faithfully rebuilt without the original source text, so reconstructed
+/// consts carry `Pos::none()`.
+pub fn decls_from_text<'a>(arena: &'a Bump, text: &str) -> Decls<'a> {
+    let mut decls = Decls::empty();
+    for line in text.lines() {
+        let mut parts = line.splitn(3, ' ');
+        let kind = match parts.next() {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let name = match parts.next() {
+            Some(name) => name,
+            None => continue,
+        };
+        let rest = match parts.next() {
+            Some(rest) => rest,
+            None => continue,
+        };
+        if kind != "const" {
+            continue;
+        }
+        // `rest` is `<pos> <ty>`; the type starts after the first space.
+        let ty_str = match rest.splitn(2, ' ').nth(1) {
+            Some(ty_str) => ty_str,
+            None => continue,
+        };
+        let ty = match parse_ty_ref(arena, ty_str) {
+            Some(ty) => ty,
+            None => continue,
+        };
+        let name = String::from_str_in(name, arena).into_bump_str();
+        let decl = arena.alloc(ConstDecl {
+            pos: Pos::none(),
+            type_: ty,
+            internal: false,
+        });
+        decls.add(name, Decl::Const(decl), arena);
+    }
+    decls
+}
+
+fn parse_ty_ref<'a>(arena: &'a Bump, s: &str) -> Option<&'a Ty<'a>> {
+    let ty_ = parse_ty(arena, s)?;
+    Some(arena.alloc(Ty(arena.alloc(Reason::none()), ty_)))
+}
+
+fn parse_ty<'a>(arena: &'a Bump, s: &str) -> Option<Ty_<'a>> {
+    match s {
+        "err" => return Some(Ty_::Terr),
+        "nonnull" => return Some(Ty_::Tnonnull),
+        "dynamic" => return Some(Ty_::Tdynamic),
+        "this" => return Some(Ty_::Tthis),
+        "any" => return Some(TANY_),
+        "nothing" => return Some(Ty_::Tunion(&[])),
+        _ => {}
+    }
+    if let Some(inner) = strip_wrapped(s, "prim(", ")") {
+        return parse_tprim(inner).map(|p| Ty_::Tprim(arena.alloc(p)));
+    }
+    if let Some(inner) = strip_wrapped(s, "generic(", ")") {
+        let name = String::from_str_in(inner, arena).into_bump_str();
+        return Some(Ty_::Tgeneric(arena.alloc((name, &[][..]))));
+    }
+    if let Some(inner) = strip_wrapped(s, "vec_or_dict(", ")") {
+        match &split_top_level_commas(inner)[..] {
+            [k, v] => {
+                let k = parse_ty_ref(arena, k)?;
+                let v = parse_ty_ref(arena, v)?;
+                return Some(Ty_::TvecOrDict(arena.alloc((k, v))));
+            }
+            _ => return None,
+        }
+    }
+    if let Some(inner) = strip_wrapped(s, "apply(", ")") {
+        let (name, targs_str) = match inner.find('<') {
+            Some(lt) if inner.ends_with('>') => (&inner[..lt], &inner[lt + 1..inner.len() - 1]),
+            _ => (inner, ""),
+        };
+        let name = String::from_str_in(name, arena).into_bump_str();
+        let mut targs = Vec::new_in(arena);
+        for part in split_top_level_commas(targs_str) {
+            if part.is_empty() {
+                continue;
+            }
+            targs.push(parse_ty_ref(arena, part)?);
+        }
+        return Some(Ty_::Tapply(
+            arena.alloc(((Pos::none(), name), targs.into_bump_slice())),
+        ));
+    }
+    None
+}
+
+fn strip_wrapped<'s>(s: &'s str, prefix: &str, suffix: &str) -> Option<&'s str> {
+    s.strip_prefix(prefix).and_then(|s| s.strip_suffix(suffix))
+}
+
+/// Splits on commas that aren't nested inside another ty's own
+/// `(...)`/`<...>`, so `apply(Foo<prim(int),apply(Bar)>)`'s argument list
+/// splits into `prim(int)` and `apply(Bar)`, not four pieces.
+fn split_top_level_commas(s: &str) -> std::vec::Vec<&str> {
+    if s.is_empty() {
+        return std::vec::Vec::new();
+    }
+    let mut parts = std::vec::Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '<' => depth += 1,
+            ')' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn parse_tprim(s: &str) -> Option<aast::Tprim> {
+    Some(match s {
+        "void" => aast::Tprim::Tvoid,
+        "int" => aast::Tprim::Tint,
+        "bool" => aast::Tprim::Tbool,
+        "float" => aast::Tprim::Tfloat,
+        "string" => aast::Tprim::Tstring,
+        "resource" => aast::Tprim::Tresource,
+        "num" => aast::Tprim::Tnum,
+        "arraykey" => aast::Tprim::Tarraykey,
+        "noreturn" => aast::Tprim::Tnoreturn,
+        "null" => aast::Tprim::Tnull,
+        _ => return None,
+    })
+}
+
 #[derive(Debug)]
 struct Modifiers {
     is_static: bool,
@@ -705,6 +1029,13 @@ pub struct UserAttributeNode<'a> {
     name: Id<'a>,
     classname_params: &'a [ClassNameParam<'a>],
     string_literal_params: &'a [&'a BStr], // this is only used for __Deprecated attribute message and Cipp parameters
+    // Every argument, converted to a constant expression the same way a
+    // const/enum initializer would be (ints, floats, bools, string
+    // literals, class constants, ...). Unlike `classname_params` and
+    // `string_literal_params` above, which only cover what specific
+    // built-in attributes need, this is the full argument list so that
+    // arbitrary user attributes round-trip into the produced decl.
+    args: &'a [&'a nast::Expr<'a>],
 }
 
 mod fixed_width_token {
@@ -719,7 +1050,11 @@ mod fixed_width_token {
     const MAX_OFFSET: u64 = !(KIND_MASK << (64 - KIND_BITS));
 
     impl FixedWidthToken {
-        pub fn new(kind: TokenKind, offset: usize) -> Self {
+        /// Returns `None` rather than panicking when `offset` doesn't fit in
+        /// the bits we have available for it (`offset > MAX_OFFSET`), so a
+        /// single pathologically large source file degrades to an ignored
+        /// token instead of aborting the whole parse.
+        pub fn new(kind: TokenKind, offset: usize) -> Option<Self> {
             // We don't want to spend bits tracking the width of fixed-width
             // tokens. Since we don't track width, verify that this token kind
             // is in fact a fixed-width kind.
@@ -727,9 +1062,9 @@ mod fixed_width_token {
 
             let offset: u64 = offset.try_into().unwrap();
             if offset > MAX_OFFSET {
-                panic!("FixedWidthToken: offset too large: {}", offset);
+                return None;
             }
-            Self(offset << KIND_BITS | kind as u8 as u64)
+            Some(Self(offset << KIND_BITS | kind as u8 as u64))
         }
 
         pub fn offset(self) -> usize {
@@ -753,6 +1088,34 @@ mod fixed_width_token {
                 .finish()
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // Any fixed-width token kind works here; the encoding doesn't look at
+        // which one it is beyond round-tripping it through `kind()`.
+        const KIND: TokenKind = TokenKind::LeftParen;
+
+        #[test]
+        fn offset_zero_round_trips() {
+            let token = FixedWidthToken::new(KIND, 0).unwrap();
+            assert_eq!(token.offset(), 0);
+            assert_eq!(token.kind(), KIND);
+        }
+
+        #[test]
+        fn offset_at_max_round_trips() {
+            let token = FixedWidthToken::new(KIND, MAX_OFFSET as usize).unwrap();
+            assert_eq!(token.offset(), MAX_OFFSET as usize);
+            assert_eq!(token.kind(), KIND);
+        }
+
+        #[test]
+        fn offset_past_max_fails_soft() {
+            assert!(FixedWidthToken::new(KIND, MAX_OFFSET as usize + 1).is_none());
+        }
+    }
 }
 use fixed_width_token::FixedWidthToken;
 
@@ -917,6 +1280,10 @@ impl<'a> Node<'a> {
             Some(TokenKind::Private) => Some(aast::Visibility::Private),
             Some(TokenKind::Protected) => Some(aast::Visibility::Protected),
             Some(TokenKind::Public) => Some(aast::Visibility::Public),
+            // `internal function f() {}` / `internal $x;` -- the keyword
+            // form of module-private visibility, as opposed to the
+            // `<<__Internal>>` attribute form handled via `attributes.internal`.
+            Some(TokenKind::Internal) => Some(aast::Visibility::Internal),
             _ => None,
         }
     }
@@ -987,6 +1354,119 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
     fn add_record(&mut self, name: &'a str, decl: &'a typing_defs::RecordDefType<'a>) {
         self.decls.add(name, Decl::Record(decl), self.arena);
     }
+    fn add_module(&mut self, name: &'a str, decl: &'a ModuleDefType<'a>) {
+        self.decls.add(name, Decl::Module(decl), self.arena);
+    }
+
+    /// A stable, line-oriented text dump of the decls collected so far, one
+    /// line per decl: `<kind> <name> <start>-<end> [<ty>]`. `<ty>` (present
+    /// for `const`/`typedef`) is rendered with `render_ty`, which covers the
+    /// same handful of `Ty_` variants `node_to_ty_` itself produces in this
+    /// parser; anything else renders as `other`. This is meant for
+    /// golden-file testing of the direct decl parser, not as a full decl
+    /// serialization format: `decls_from_text` below only reconstructs
+    /// `const` decls faithfully (the only kind simple enough -- a `Pos` plus
+    /// a `Ty` -- to round-trip without fabricating the dozens of other
+    /// fields `ShallowClass`/`FunElt`/`RecordDefType` carry); `class`/`fun`/
+    /// `record`/`module` lines record enough to diff existence, name, and
+    /// span.
+    pub fn dump_decls(&self) -> std::string::String {
+        let mut out = std::string::String::new();
+        for (name, decl) in self.decls.iter() {
+            match decl {
+                Decl::Class(c) => {
+                    self.dump_decl_line(&mut out, "class", name, c.name.0);
+                }
+                Decl::Fun(f) => {
+                    self.dump_decl_line(&mut out, "fun", name, f.pos);
+                }
+                Decl::Typedef(t) => {
+                    self.dump_decl_line(&mut out, "typedef", name, t.pos);
+                    out.push(' ');
+                    out.push_str(&self.render_ty(t.type_));
+                }
+                Decl::Const(c) => {
+                    self.dump_decl_line(&mut out, "const", name, c.pos);
+                    out.push(' ');
+                    out.push_str(&self.render_ty(c.type_));
+                }
+                Decl::Record(r) => {
+                    self.dump_decl_line(&mut out, "record", name, r.pos);
+                }
+                Decl::Module(m) => {
+                    self.dump_decl_line(&mut out, "module", name, m.pos);
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    fn dump_decl_line(
+        &self,
+        out: &mut std::string::String,
+        kind: &str,
+        name: &str,
+        pos: &'a Pos<'a>,
+    ) {
+        out.push_str(kind);
+        out.push(' ');
+        out.push_str(name);
+        out.push(' ');
+        if pos.is_none() {
+            out.push_str("none");
+        } else {
+            out.push_str(&std::format!("{}-{}", pos.start_cnum(), pos.end_cnum()));
+        }
+    }
+
+    fn render_ty(&self, ty: &Ty<'a>) -> std::string::String {
+        self.render_ty_(ty.1)
+    }
+
+    fn render_ty_(&self, ty_: &Ty_<'a>) -> std::string::String {
+        match ty_ {
+            Ty_::Terr => "err".to_string(),
+            Ty_::Tnonnull => "nonnull".to_string(),
+            Ty_::Tdynamic => "dynamic".to_string(),
+            Ty_::Tthis => "this".to_string(),
+            Ty_::Tany(_) => "any".to_string(),
+            Ty_::Tunion(&[]) => "nothing".to_string(),
+            Ty_::Tprim(p) => std::format!("prim({})", Self::render_tprim(p)),
+            Ty_::Tapply(&((_, name), targs)) if targs.is_empty() => {
+                std::format!("apply({})", name)
+            }
+            Ty_::Tapply(&((_, name), targs)) => std::format!(
+                "apply({}<{}>)",
+                name,
+                targs
+                    .iter()
+                    .map(|t| self.render_ty(t))
+                    .collect::<std::vec::Vec<_>>()
+                    .join(",")
+            ),
+            Ty_::Tgeneric(&(name, &[])) => std::format!("generic({})", name),
+            Ty_::TvecOrDict(&(k, v)) => {
+                std::format!("vec_or_dict({},{})", self.render_ty(k), self.render_ty(v))
+            }
+            _ => "other".to_string(),
+        }
+    }
+
+    fn render_tprim(p: &aast::Tprim) -> &'static str {
+        match p {
+            aast::Tprim::Tvoid => "void",
+            aast::Tprim::Tint => "int",
+            aast::Tprim::Tbool => "bool",
+            aast::Tprim::Tfloat => "float",
+            aast::Tprim::Tstring => "string",
+            aast::Tprim::Tresource => "resource",
+            aast::Tprim::Tnum => "num",
+            aast::Tprim::Tarraykey => "arraykey",
+            aast::Tprim::Tnoreturn => "noreturn",
+            aast::Tprim::Tnull => "null",
+        }
+    }
 
     #[inline]
     fn concat(&self, str1: &str, str2: &str) -> &'a str {
@@ -1021,6 +1501,13 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
         }
     }
 
+    // Copies a freshly-computed `std::string::String` into the arena. Unlike
+    // `str_from_utf8`, there's no chance of referencing the source buffer
+    // directly since the string didn't come from the source text.
+    fn alloc_str(&self, s: &str) -> &'a str {
+        String::from_str_in(s, self.arena).into_bump_str()
+    }
+
     fn merge(
         &self,
         pos1: impl Into<Option<&'a Pos<'a>>>,
@@ -1106,6 +1593,203 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
         Some(self.alloc(aast::Expr((), pos, expr_)))
     }
 
+    /// Attempts to fold `expr` to a single literal `Node`, for use by the
+    /// smart constructors that build `Unop`/`Binop` expressions: a constant
+    /// sub-expression collapses to a literal node instead of staying an
+    /// unevaluated `Node::Expr`, e.g. so `const int X = 1 + 2;` sees `3`
+    /// rather than an addition. Returns `None` (leaving the original tree in
+    /// place) when any operand isn't statically known.
+    fn try_fold_const_expr(&self, expr: &'a nast::Expr<'a>) -> Option<Node<'a>> {
+        let value = Self::eval_const_expr(expr)?;
+        let pos = expr.1;
+        Some(match value {
+            ConstFoldValue::Int(i) => {
+                Node::IntLiteral(self.alloc((self.alloc_str(&i.to_string()), pos)))
+            }
+            ConstFoldValue::Float(f) => Node::FloatingLiteral(
+                self.alloc((self.alloc_str(&Self::format_hack_float(f)), pos)),
+            ),
+            ConstFoldValue::Str(s) => {
+                Node::StringLiteral(self.alloc((BStr::new(self.alloc_str(&s).as_bytes()), pos)))
+            }
+            ConstFoldValue::Bool(b) => {
+                Node::BooleanLiteral(self.alloc((if b { "true" } else { "false" }, pos)))
+            }
+        })
+    }
+
+    fn format_hack_float(f: f64) -> std::string::String {
+        if f == f.trunc() && f.is_finite() {
+            std::format!("{:.1}", f)
+        } else {
+            std::format!("{}", f)
+        }
+    }
+
+    /// Parses the raw token text of a decimal/hex/octal/binary int literal
+    /// into its value. A literal that fits in 64 bits but not in `i64`
+    /// wraps (e.g. `0xFFFFFFFFFFFFFFFF` is `-1`); a wider one yields `None`.
+    fn parse_hack_int_literal(text: &str) -> Option<i64> {
+        let cleaned: std::string::String = text.chars().filter(|c| *c != '_').collect();
+        let (radix, digits): (u32, &str) = if let Some(rest) = cleaned
+            .strip_prefix("0x")
+            .or_else(|| cleaned.strip_prefix("0X"))
+        {
+            (16, rest)
+        } else if let Some(rest) = cleaned
+            .strip_prefix("0b")
+            .or_else(|| cleaned.strip_prefix("0B"))
+        {
+            (2, rest)
+        } else if let Some(rest) = cleaned
+            .strip_prefix("0o")
+            .or_else(|| cleaned.strip_prefix("0O"))
+        {
+            (8, rest)
+        } else if cleaned.len() > 1 && cleaned.starts_with('0') {
+            (8, &cleaned[1..])
+        } else {
+            (10, cleaned.as_str())
+        };
+        i64::from_str_radix(digits, radix)
+            .ok()
+            .or_else(|| u64::from_str_radix(digits, radix).ok().map(|u| u as i64))
+    }
+
+    fn const_fold_truthy(value: &ConstFoldValue) -> bool {
+        match value {
+            ConstFoldValue::Int(i) => *i != 0,
+            ConstFoldValue::Float(f) => *f != 0.0,
+            ConstFoldValue::Str(s) => !s.is_empty() && s != "0",
+            ConstFoldValue::Bool(b) => *b,
+        }
+    }
+
+    fn const_fold_to_string(value: &ConstFoldValue) -> std::string::String {
+        match value {
+            ConstFoldValue::Int(i) => i.to_string(),
+            ConstFoldValue::Float(f) => Self::format_hack_float(*f),
+            ConstFoldValue::Str(s) => s.clone(),
+            ConstFoldValue::Bool(b) => {
+                if *b {
+                    "1".to_string()
+                } else {
+                    "".to_string()
+                }
+            }
+        }
+    }
+
+    /// Recursively evaluates a constant `aast::Expr_`, folding it to a single
+    /// leaf value when every operand is statically known. Any operand this
+    /// doesn't recognize (a `ClassConst`, `Id`, call, etc.) makes the whole
+    /// expression non-foldable, so callers must leave the original tree in
+    /// place rather than discard it.
+    fn eval_const_expr(expr: &'a nast::Expr<'a>) -> Option<ConstFoldValue> {
+        use aast::Expr_::*;
+        match expr.2 {
+            Int(s) => Self::parse_hack_int_literal(s).map(ConstFoldValue::Int),
+            Float(s) => s.parse::<f64>().ok().map(ConstFoldValue::Float),
+            String(s) => std::str::from_utf8(s)
+                .ok()
+                .map(|s| ConstFoldValue::Str(s.to_string())),
+            True => Some(ConstFoldValue::Bool(true)),
+            False => Some(ConstFoldValue::Bool(false)),
+            Unop(&(op, operand)) => {
+                let v = Self::eval_const_expr(operand)?;
+                match (op, v) {
+                    (Uop::Uminus, ConstFoldValue::Int(i)) => {
+                        Some(ConstFoldValue::Int(i.wrapping_neg()))
+                    }
+                    (Uop::Uminus, ConstFoldValue::Float(f)) => Some(ConstFoldValue::Float(-f)),
+                    (Uop::Uplus, v @ (ConstFoldValue::Int(_) | ConstFoldValue::Float(_))) => {
+                        Some(v)
+                    }
+                    (Uop::Utild, ConstFoldValue::Int(i)) => Some(ConstFoldValue::Int(!i)),
+                    (Uop::Unot, ConstFoldValue::Bool(b)) => Some(ConstFoldValue::Bool(!b)),
+                    (Uop::Unot, v) => Some(ConstFoldValue::Bool(!Self::const_fold_truthy(&v))),
+                    _ => None,
+                }
+            }
+            Binop(&(ref op, lhs, rhs)) => {
+                let lhs = Self::eval_const_expr(lhs)?;
+                match op {
+                    Bop::Ampamp => {
+                        if !Self::const_fold_truthy(&lhs) {
+                            return Some(ConstFoldValue::Bool(false));
+                        }
+                        let rhs = Self::eval_const_expr(rhs)?;
+                        Some(ConstFoldValue::Bool(Self::const_fold_truthy(&rhs)))
+                    }
+                    Bop::Barbar => {
+                        if Self::const_fold_truthy(&lhs) {
+                            return Some(ConstFoldValue::Bool(true));
+                        }
+                        let rhs = Self::eval_const_expr(rhs)?;
+                        Some(ConstFoldValue::Bool(Self::const_fold_truthy(&rhs)))
+                    }
+                    Bop::Dot => {
+                        let rhs = Self::eval_const_expr(rhs)?;
+                        Some(ConstFoldValue::Str(std::format!(
+                            "{}{}",
+                            Self::const_fold_to_string(&lhs),
+                            Self::const_fold_to_string(&rhs)
+                        )))
+                    }
+                    _ => {
+                        let rhs = Self::eval_const_expr(rhs)?;
+                        Self::eval_const_arith_binop(op, lhs, rhs)
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn eval_const_arith_binop(
+        op: &Bop,
+        lhs: ConstFoldValue,
+        rhs: ConstFoldValue,
+    ) -> Option<ConstFoldValue> {
+        use ConstFoldValue::*;
+        if matches!(lhs, Float(_)) || matches!(rhs, Float(_)) {
+            let lf = match lhs {
+                Float(f) => f,
+                Int(i) => i as f64,
+                _ => return None,
+            };
+            let rf = match rhs {
+                Float(f) => f,
+                Int(i) => i as f64,
+                _ => return None,
+            };
+            return match op {
+                Bop::Plus => Some(Float(lf + rf)),
+                Bop::Minus => Some(Float(lf - rf)),
+                Bop::Star => Some(Float(lf * rf)),
+                Bop::Slash if rf != 0.0 => Some(Float(lf / rf)),
+                _ => None,
+            };
+        }
+        let (li, ri) = match (lhs, rhs) {
+            (Int(li), Int(ri)) => (li, ri),
+            _ => return None,
+        };
+        match op {
+            Bop::Plus => Some(Int(li.wrapping_add(ri))),
+            Bop::Minus => Some(Int(li.wrapping_sub(ri))),
+            Bop::Star => Some(Int(li.wrapping_mul(ri))),
+            Bop::Slash if ri != 0 && li.wrapping_rem(ri) == 0 => Some(Int(li.wrapping_div(ri))),
+            Bop::Percent if ri != 0 => Some(Int(li.wrapping_rem(ri))),
+            Bop::Ltlt => Some(Int(li.wrapping_shl(ri as u32))),
+            Bop::Gtgt => Some(Int(li.wrapping_shr(ri as u32))),
+            Bop::Amp => Some(Int(li & ri)),
+            Bop::Bar => Some(Int(li | ri)),
+            Bop::Xor => Some(Int(li ^ ri)),
+            _ => None,
+        }
+    }
+
     fn node_to_non_ret_ty(&self, node: Node<'a>) -> Option<&'a Ty<'a>> {
         self.node_to_ty_(node, false)
     }
@@ -1117,10 +1801,10 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
     fn node_to_ty_(&self, node: Node<'a>, allow_non_ret_ty: bool) -> Option<&'a Ty<'a>> {
         match node {
             Node::Ty(Ty(reason, Ty_::Tprim(aast::Tprim::Tvoid))) if !allow_non_ret_ty => {
-                Some(self.alloc(Ty(reason, Ty_::Terr)))
+                Some(self.intern_ty(reason, Ty_::Terr))
             }
             Node::Ty(Ty(reason, Ty_::Tprim(aast::Tprim::Tnoreturn))) if !allow_non_ret_ty => {
-                Some(self.alloc(Ty(reason, Ty_::Terr)))
+                Some(self.intern_ty(reason, Ty_::Terr))
             }
             Node::Ty(ty) => Some(ty),
             Node::Expr(expr) => {
@@ -1188,6 +1872,21 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
                 )));
                 Some(self.alloc(Ty(self.alloc(Reason::hint(pos)), ty_)))
             }
+            // Bare, untyped `array` -- unlike `varray`/`darray` above (which
+            // always mean a single concrete shape), legacy `array` could
+            // hold either a vec-like or dict-like value, so under
+            // `unify_varray_and_darray` it lowers to `vec_or_dict` with an
+            // `arraykey` key and an untyped (`Tany`) value rather than
+            // picking one. When the option is off,
+            // fall through to the generic name-based path below, same as
+            // any other class name spelled "array".
+            Node::Token(t) if t.kind() == TokenKind::Array && self.opts.unify_varray_and_darray => {
+                let pos = self.token_pos(t);
+                let key_type = self.vec_or_dict_key(pos);
+                let value_type = self.alloc(Ty(self.alloc(Reason::hint(pos)), TANY_));
+                let ty_ = Ty_::TvecOrDict(self.alloc((key_type, value_type)));
+                Some(self.alloc(Ty(self.alloc(Reason::hint(pos)), ty_)))
+            }
             Node::Token(t) if t.kind() == TokenKind::This => {
                 Some(self.alloc(Ty(self.alloc(Reason::hint(self.token_pos(t))), Ty_::Tthis)))
             }
@@ -1228,7 +1927,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
                         }
                     }
                 };
-                Some(self.alloc(Ty(reason, ty_)))
+                Some(self.intern_ty(reason, ty_))
             }
         }
     }
@@ -1345,16 +2044,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
                             .string_literal_params
                             .first()
                             .map(|&x| self.str_from_utf8_for_bytes_in_arena(x))
-                            .and_then(|x| {
-                                let mut chars = x.split('.');
-                                match chars.next() {
-                                    None => None,
-                                    Some(s) => {
-                                        let rest = chars.collect::<std::vec::Vec<_>>();
-                                        Some(self.alloc(Module_(s, self.alloc(rest))))
-                                    }
-                                }
-                            });
+                            .and_then(|x| self.parse_dotted_module_name(x));
                     }
                     "__Internal" => {
                         attributes.internal = true;
@@ -1384,10 +2074,56 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
                     Ty_::Tprim(self.alloc(aast::Tprim::Tnull)),
                 )))
             }
+            Node::Expr(aast::Expr(_, _, aast::Expr_::Binop(&(op, e1, e2)))) => {
+                self.infer_const_binop(name, op, e1, e2)
+            }
             _ => Some(self.tany_with_pos(self.get_pos(name))),
         }
     }
 
+    /// Shallow folding of a const initializer's top-level binary operation,
+    /// mirroring `Decl_utils.infer_const`: we only need the *type* the
+    /// operation produces, not its value, so this recurses on each operand's
+    /// own `infer_const` rather than evaluating anything (that's
+    /// `try_fold_const_expr`'s job). Operands we can't see through (class
+    /// constants, names, ...) infer to `tany`, which is contagious for
+    /// arithmetic: any arithmetic touching one falls back to `tany` too.
+    /// Concatenation always infers `string` and comparisons always infer
+    /// `bool`, whatever their operands.
+    fn infer_const_binop(
+        &self,
+        name: Node<'a>,
+        op: Bop,
+        e1: &'a nast::Expr<'a>,
+        e2: &'a nast::Expr<'a>,
+    ) -> Option<&'a Ty<'a>> {
+        let pos = self.get_pos(name);
+        let prim_ty = |prim| self.alloc(Ty(self.alloc(Reason::witness_from_decl(pos)), prim));
+        fn as_numeric_prim<'a>(ty: &'a Ty<'a>) -> Option<&'a aast::Tprim> {
+            match ty.1 {
+                Ty_::Tprim(prim @ (aast::Tprim::Tint | aast::Tprim::Tfloat)) => Some(prim),
+                _ => None,
+            }
+        }
+        match op {
+            Bop::Plus | Bop::Minus | Bop::Star | Bop::Slash | Bop::Starstar => {
+                let t1 = self.infer_const(name, Node::Expr(e1))?;
+                let t2 = self.infer_const(name, Node::Expr(e2))?;
+                match (as_numeric_prim(t1), as_numeric_prim(t2)) {
+                    (Some(aast::Tprim::Tint), Some(aast::Tprim::Tint)) => {
+                        Some(prim_ty(Ty_::Tprim(self.alloc(aast::Tprim::Tint))))
+                    }
+                    (Some(_), Some(_)) => Some(prim_ty(Ty_::Tprim(self.alloc(aast::Tprim::Tfloat)))),
+                    _ => Some(self.tany_with_pos(pos)),
+                }
+            }
+            Bop::Dot => Some(prim_ty(Ty_::Tprim(self.alloc(aast::Tprim::Tstring)))),
+            Bop::Eqeq | Bop::Eqeqeq | Bop::Diff | Bop::Diff2 | Bop::Lt | Bop::Lte | Bop::Gt
+            | Bop::Gte => Some(prim_ty(Ty_::Tprim(self.alloc(aast::Tprim::Tbool)))),
+            _ => Some(self.tany_with_pos(pos)),
+        }
+    }
+
     fn pop_type_params(&mut self, node: Node<'a>) -> &'a [&'a Tparam<'a>] {
         match node {
             Node::TypeParameters(tparams) => {
@@ -1778,7 +2514,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
     }
 
     fn hint_ty(&self, pos: &'a Pos<'a>, ty_: Ty_<'a>) -> Node<'a> {
-        Node::Ty(self.alloc(Ty(self.alloc(Reason::hint(pos)), ty_)))
+        Node::Ty(self.intern_ty(self.alloc(Reason::hint(pos)), ty_))
     }
 
     fn prim_ty(&self, tprim: aast::Tprim, pos: &'a Pos<'a>) -> Node<'a> {
@@ -1811,11 +2547,11 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
     fn convert_tapply_to_tgeneric(&self, ty: &'a Ty<'a>) -> &'a Ty<'a> {
         let ty_ = match ty.1 {
             Ty_::Tapply(&(id, targs)) => {
-                let converted_targs = self.slice(
-                    targs
-                        .iter()
-                        .map(|&targ| self.convert_tapply_to_tgeneric(targ)),
-                );
+                let converted: std::vec::Vec<_> = targs
+                    .iter()
+                    .map(|&targ| self.convert_tapply_to_tgeneric(targ))
+                    .collect();
+                let converted_targs = self.intern_targs(&converted);
                 match self.tapply_should_be_tgeneric(ty.0, id) {
                     Some(name) => Ty_::Tgeneric(self.alloc((name, converted_targs))),
                     None => Ty_::Tapply(self.alloc((id, converted_targs))),
@@ -1877,12 +2613,13 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
                 self.convert_tapply_to_tgeneric(tk),
                 self.convert_tapply_to_tgeneric(tv),
             ))),
-            Ty_::Ttuple(tys) => Ty_::Ttuple(
-                self.slice(
-                    tys.iter()
-                        .map(|&targ| self.convert_tapply_to_tgeneric(targ)),
-                ),
-            ),
+            Ty_::Ttuple(tys) => {
+                let converted: std::vec::Vec<_> = tys
+                    .iter()
+                    .map(|&targ| self.convert_tapply_to_tgeneric(targ))
+                    .collect();
+                Ty_::Ttuple(self.intern_targs(&converted))
+            }
             _ => return ty,
         };
         self.alloc(Ty(ty.0, ty_))
@@ -1928,6 +2665,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>> DirectDeclSmartConstructors<'
         self.alloc(shallow_decl_defs::UserAttribute {
             name: attr.name.into(),
             classname_params: self.slice(attr.classname_params.iter().map(|p| p.name.1)),
+            args: attr.args,
         })
     }
 
@@ -2530,7 +3268,8 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             | TokenKind::XHP
             | TokenKind::Required
             | TokenKind::Ctx
-            | TokenKind::Readonly => Node::Token(FixedWidthToken::new(kind, token.start_offset())),
+            | TokenKind::Readonly => FixedWidthToken::new(kind, token.start_offset())
+                .map_or(Node::Ignored(SK::Token(kind)), Node::Token),
             TokenKind::EndOfFile
             | TokenKind::Attribute
             | TokenKind::Await
@@ -2689,7 +3428,30 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
     }
 
     fn make_literal_expression(&mut self, expression: Self::R) -> Self::R {
-        expression
+        // Normalize the token's raw text into a canonical literal value:
+        // strip `_` digit separators and decode `0x`/`0o`/`0b` radix
+        // prefixes for ints, and collapse underscore separators for floats.
+        // String literals are already escape-decoded by `make_token`, so
+        // they pass through unchanged. A literal that can't be parsed (e.g.
+        // an int too wide for 64 bits) degrades to `Ignored` rather than carrying
+        // bogus raw text forward.
+        match expression {
+            Node::IntLiteral(&(text, pos)) => match Self::parse_hack_int_literal(text) {
+                Some(i) => Node::IntLiteral(self.alloc((self.alloc_str(&i.to_string()), pos))),
+                None => Node::Ignored(SK::LiteralExpression),
+            },
+            Node::FloatingLiteral(&(text, pos)) => {
+                let cleaned: std::string::String =
+                    text.chars().filter(|c| *c != '_').collect();
+                match cleaned.parse::<f64>() {
+                    Ok(f) => Node::FloatingLiteral(
+                        self.alloc((self.alloc_str(&Self::format_hack_float(f)), pos)),
+                    ),
+                    Err(_) => Node::Ignored(SK::LiteralExpression),
+                }
+            }
+            node => node,
+        }
     }
 
     fn make_simple_initializer(&mut self, equals: Self::R, expr: Self::R) -> Self::R {
@@ -2766,11 +3528,12 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             Some(value) => value,
             None => return Node::Ignored(SK::PrefixUnaryExpression),
         };
-        Node::Expr(self.alloc(aast::Expr(
+        let expr = self.alloc(aast::Expr(
             (),
             pos,
             aast::Expr_::Unop(self.alloc((op, value))),
-        )))
+        ));
+        self.try_fold_const_expr(expr).unwrap_or(Node::Expr(expr))
     }
 
     fn make_postfix_unary_expression(&mut self, value: Self::R, op: Self::R) -> Self::R {
@@ -2813,6 +3576,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             Some(TokenKind::Ampersand) => Bop::Amp,
             Some(TokenKind::Bar) => Bop::Bar,
             Some(TokenKind::Percent) => Bop::Percent,
+            Some(TokenKind::Caret) => Bop::Xor,
             Some(TokenKind::QuestionQuestion) => Bop::QuestionQuestion,
             _ => return Node::Ignored(SK::BinaryExpression),
         };
@@ -2833,11 +3597,12 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             None => return Node::Ignored(SK::BinaryExpression),
         };
 
-        Node::Expr(self.alloc(aast::Expr(
+        let expr = self.alloc(aast::Expr(
             (),
             pos,
             aast::Expr_::Binop(self.alloc((op, lhs, rhs))),
-        )))
+        ));
+        self.try_fold_const_expr(expr).unwrap_or(Node::Expr(expr))
     }
 
     fn make_parenthesized_expression(
@@ -2905,6 +3670,86 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                 };
                 self.hint_ty(pos, ty_)
             }
+            // Under `unify_varray_and_darray`, rewrite the legacy `varray<T>`
+            // / `darray<Tk, Tv>` spellings to the `vec<T>` / `dict<Tk, Tv>`
+            // representation so downstream typing sees a single array
+            // family, mirroring the `varray_or_darray`/`vec_or_dict` case
+            // above. When the option is off, fall through to the generic
+            // `make_apply` path below, same as any other class name.
+            "varray" if self.opts.unify_varray_and_darray => {
+                let id_pos = class_id.0;
+                let pos = self.merge(id_pos, self.get_pos(type_arguments));
+                let type_arguments = type_arguments.as_slice(self.arena);
+                let tv = match type_arguments {
+                    [tv] => self
+                        .node_to_ty(*tv)
+                        .unwrap_or_else(|| self.tany_with_pos(id_pos)),
+                    _ => self.tany_with_pos(id_pos),
+                };
+                let ty_ = Ty_::Tapply(self.alloc((
+                    (id_pos, naming_special_names::collections::VEC),
+                    self.alloc([tv]) as &[_],
+                )));
+                self.hint_ty(pos, ty_)
+            }
+            "darray" if self.opts.unify_varray_and_darray => {
+                let id_pos = class_id.0;
+                let pos = self.merge(id_pos, self.get_pos(type_arguments));
+                let type_arguments = type_arguments.as_slice(self.arena);
+                let (tk, tv) = match type_arguments {
+                    [tk, tv] => (
+                        self.node_to_ty(*tk)
+                            .unwrap_or_else(|| self.tany_with_pos(id_pos)),
+                        self.node_to_ty(*tv)
+                            .unwrap_or_else(|| self.tany_with_pos(id_pos)),
+                    ),
+                    [tv] => (
+                        self.vec_or_dict_key(pos),
+                        self.node_to_ty(*tv)
+                            .unwrap_or_else(|| self.tany_with_pos(id_pos)),
+                    ),
+                    _ => (self.tany_with_pos(id_pos), self.tany_with_pos(id_pos)),
+                };
+                let ty_ = Ty_::Tapply(self.alloc((
+                    (id_pos, naming_special_names::collections::DICT),
+                    self.alloc([tk, tv]) as &[_],
+                )));
+                self.hint_ty(pos, ty_)
+            }
+            // `array<T>` / `array<Tk, Tv>` with explicit type arguments --
+            // unlike bare `array` (handled in `node_to_ty_`, which always
+            // means "could be either shape"), an explicit single argument
+            // means vec-like and a pair means dict-like, same arity rule
+            // `varray`/`darray` follow above.
+            "array" if self.opts.unify_varray_and_darray => {
+                let id_pos = class_id.0;
+                let pos = self.merge(id_pos, self.get_pos(type_arguments));
+                let type_arguments = type_arguments.as_slice(self.arena);
+                let ty_ = match type_arguments {
+                    [tv] => Ty_::Tapply(self.alloc((
+                        (id_pos, naming_special_names::collections::VEC),
+                        self.alloc([self
+                            .node_to_ty(*tv)
+                            .unwrap_or_else(|| self.tany_with_pos(id_pos))])
+                            as &[_],
+                    ))),
+                    [tk, tv] => Ty_::Tapply(self.alloc((
+                        (id_pos, naming_special_names::collections::DICT),
+                        self.alloc([
+                            self.node_to_ty(*tk)
+                                .unwrap_or_else(|| self.tany_with_pos(id_pos)),
+                            self.node_to_ty(*tv)
+                                .unwrap_or_else(|| self.tany_with_pos(id_pos)),
+                        ]) as &[_],
+                    ))),
+                    _ => {
+                        let key_type = self.vec_or_dict_key(pos);
+                        let value_type = self.tany_with_pos(id_pos);
+                        Ty_::TvecOrDict(self.alloc((key_type, value_type)))
+                    }
+                };
+                self.hint_ty(pos, ty_)
+            }
             _ => {
                 let Id(pos, class_type) = class_id;
                 match class_type.rsplit('\\').next() {
@@ -2947,10 +3792,11 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             Some(name) => name,
             None => return Node::Ignored(SK::RecordDeclaration),
         };
+        let parsed_attributes = self.to_attributes(attribute_spec);
         self.add_record(
             name.1,
             self.alloc(typing_defs::RecordDefType {
-                module: &None, // TODO: grab module from attributes
+                module: self.alloc(self.resolve_module(parsed_attributes.module)),
                 name: name.into(),
                 extends: self
                     .expect_name(extends_opt)
@@ -3015,7 +3861,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         let tparams = self.pop_type_params(generic_params);
         let parsed_attributes = self.to_attributes(attributes);
         let typedef = self.alloc(TypedefType {
-            module: self.alloc(parsed_attributes.module),
+            module: self.alloc(self.resolve_module(parsed_attributes.module)),
             pos,
             vis: if parsed_attributes.internal {
                 aast::TypedefVisibility::Tinternal
@@ -3078,7 +3924,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         let tparams = self.pop_type_params(generic_params);
         let parsed_attributes = self.to_attributes(attributes);
         let typedef = self.alloc(TypedefType {
-            module: self.alloc(parsed_attributes.module),
+            module: self.alloc(self.resolve_module(parsed_attributes.module)),
             pos,
             vis: if parsed_attributes.internal {
                 aast::TypedefVisibility::Tinternal
@@ -3138,13 +3984,12 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             _ => None,
         }));
 
-        // TODO(T70068435) Once we add support for constraints on higher-kinded types
-        // (in particular, constraints on nested type parameters), we need to ensure
-        // that we correctly handle the scoping of nested type parameters.
-        // This includes making sure that the call to convert_type_appl_to_generic
-        // in make_type_parameters handles nested constraints.
-        // For now, we just make sure that the nested type parameters that make_type_parameters
-        // added to the global list of in-scope type parameters are removed immediately:
+        // (T70068435) The nested type parameters that make_type_parameters
+        // added to the global list of in-scope type parameters are removed
+        // immediately here; make_type_parameters re-pushes this tparam's own
+        // `tparam_params` names for just long enough to convert this
+        // tparam's constraints, so e.g. `T<Tk as C>` resolves `Tk` to a
+        // `Tgeneric` rather than a `Tapply` there.
         self.pop_type_params(tparam_params);
 
         let tparam_params = match tparam_params {
@@ -3202,12 +4047,25 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                 tparam_params,
                 user_attributes,
             } = decl;
+            // A higher-kinded tparam's own nested params (e.g. `Tk` in
+            // `T<Tk as C>`) were already popped off the in-scope list by
+            // `make_type_parameter` before we get here (see its
+            // T70068435 comment), so `Tk` would otherwise be misresolved
+            // to a `Tapply` instead of a `Tgeneric` by
+            // `convert_tapply_to_tgeneric` below. Re-push them just long
+            // enough to convert this tparam's own constraints.
+            let mut nested_names = MultiSetMut::with_capacity_in(tparam_params.len(), self.arena);
+            for nested in tparam_params.iter() {
+                nested_names.insert(nested.name.1);
+            }
+            Rc::make_mut(&mut self.type_parameters).push(nested_names.into());
             let constraints = self.slice(constraints.iter().filter_map(|constraint| {
                 let &(kind, ty) = constraint;
                 let ty = self.node_to_ty(ty)?;
                 let ty = self.convert_tapply_to_tgeneric(ty);
                 Some((kind, ty))
             }));
+            Rc::make_mut(&mut self.type_parameters).pop().unwrap();
 
             let user_attributes = self.slice(
                 user_attributes
@@ -3327,9 +4185,18 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                     s.push_str(msg);
                     s.into_bump_str()
                 });
+                // `internal` can be written either as the `<<__Internal>>`
+                // attribute (already captured in `parsed_attributes`) or as
+                // a keyword modifier directly on the function header
+                // (`internal function f(): void {}`).
+                let internal = parsed_attributes.internal
+                    || header
+                        .modifiers
+                        .iter()
+                        .any(|node| node.is_token(TokenKind::Internal));
                 let fun_elt = self.alloc(FunElt {
-                    module: self.alloc(parsed_attributes.module),
-                    internal: parsed_attributes.internal,
+                    module: self.alloc(self.resolve_module(parsed_attributes.module)),
+                    internal,
                     deprecated,
                     type_,
                     pos,
@@ -3463,6 +4330,13 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         decls: Self::R,
         semicolon: Self::R,
     ) -> Self::R {
+        // `internal` is a modifier keyword here (consts have no attribute
+        // list of their own), read the same way `is_abstract`/`is_static`
+        // are read off `modifiers` -- mirroring how `make_function_declaration`
+        // reads `parsed_attributes.internal` off its attribute list.
+        let internal = modifiers
+            .iter()
+            .any(|node| node.is_token(TokenKind::Internal));
         match decls {
             // Class consts.
             Node::List(consts)
@@ -3491,6 +4365,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                                     name: id.into(),
                                     type_: ty,
                                     refs,
+                                    internal,
                                 },
                             )))
                         }
@@ -3516,7 +4391,14 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                                     .node_to_ty(hint)
                                     .or_else(|| self.infer_const(name, initializer))
                                     .unwrap_or_else(|| self.tany_with_pos(id_pos));
-                                self.add_const(id, self.alloc(ConstDecl { pos, type_: ty }));
+                                self.add_const(
+                                    id,
+                                    self.alloc(ConstDecl {
+                                        pos,
+                                        type_: ty,
+                                        internal,
+                                    }),
+                                );
                             }
                         }
                         _ => {}
@@ -3569,6 +4451,54 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         Node::Ignored(SK::NamespaceBody)
     }
 
+    /// `module Foo.Bar;` -- declares which module every subsequent decl in
+    /// this file belongs to. A file may declare at most one membership; a
+    /// later one simply overwrites the earlier one's effect on decls built
+    /// after it, the same way OCaml decl treats it as a file-level setting.
+    ///
+    /// Deliberately *not* run through `elaborate_defined_id`: module names
+    /// live in their own flat namespace, unaffected by the file's `namespace`
+    /// statements, unlike class/function/const names.
+    fn make_module_membership_declaration(
+        &mut self,
+        _module_keyword: Self::R,
+        name: Self::R,
+        _semicolon: Self::R,
+    ) -> Self::R {
+        if let Some(Id(_, name)) = self.expect_name(name) {
+            self.file_module = self.parse_dotted_module_name(name);
+        }
+        Node::Ignored(SK::ModuleMembershipDeclaration)
+    }
+
+    /// `new module Foo.Bar {}` -- defines a module, registered in `Decls` so
+    /// consumers can tell which modules exist (as opposed to merely which
+    /// module a given file is a member of). The brace-delimited body (export
+    /// and import clauses) isn't surfaced to this constructor -- only the
+    /// name and the braces' positions are -- so it can't be recorded here;
+    /// decls from class/enum declarations already pick up their module via
+    /// `resolve_module`, which is all the checker needs from this file.
+    fn make_module_declaration(
+        &mut self,
+        _attribute_spec: Self::R,
+        module_keyword: Self::R,
+        name: Self::R,
+        _left_brace: Self::R,
+        right_brace: Self::R,
+    ) -> Self::R {
+        let name = match self.expect_name(name) {
+            Some(name) => name,
+            None => return Node::Ignored(SK::ModuleDeclaration),
+        };
+        self.add_module(
+            name.1,
+            self.alloc(ModuleDefType {
+                pos: self.merge_positions(module_keyword, right_brace),
+            }),
+        );
+        Node::Ignored(SK::ModuleDeclaration)
+    }
+
     fn make_namespace_empty_body(&mut self, _semicolon: Self::R) -> Self::R {
         Rc::make_mut(&mut self.namespace_builder).pop_previous_namespace();
         Node::Ignored(SK::NamespaceEmptyBody)
@@ -3709,6 +4639,16 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                     class_kind = ClassishKind::Cclass(&Abstraction::Abstract)
                 }
                 Some(TokenKind::Final) => final_ = true,
+                // `internal class Foo {}` would combine freely with
+                // `abstract`/`final` here, same as those two -- but
+                // `ShallowClass` (defined outside this crate) has no
+                // internal/module-private field to record it in today
+                // (unlike `ShallowMethod`/`ShallowProp`, which reuse
+                // `Visibility::Internal`, or `FunElt`/`TypedefType`, which
+                // have their own `internal`/`vis` fields). TODO: confirm with
+                // the `ShallowClass` owners whether module-private classes
+                // are in scope at all -- if so, add the field there first;
+                // until then there's nothing to thread the keyword into.
                 _ => {}
             }
         }
@@ -3904,7 +4844,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             self.opts.everything_sdt || class_attributes.support_dynamic_type;
         // Pop the type params stack only after creating all inner types.
         let tparams = self.pop_type_params(tparams);
-        let module = class_attributes.module;
+        let module = self.resolve_module(class_attributes.module);
 
         let cls = self.alloc(shallow_decl_defs::ShallowClass {
             mode: self.file_mode,
@@ -3989,11 +4929,20 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                     flags.set(PropFlags::ABSTRACT, modifiers.is_abstract);
                     flags.set(PropFlags::READONLY, modifiers.is_readonly);
                     flags.set(PropFlags::PHP_STD_LIB, attributes.php_std_lib);
+                    // Mirror `make_methodish_declaration`'s Public -> Internal
+                    // promotion so module-access checking treats properties
+                    // the same way it treats methods.
+                    let visibility = match modifiers.visibility {
+                        aast::Visibility::Public if attributes.internal => {
+                            aast::Visibility::Internal
+                        }
+                        vis => vis,
+                    };
                     Some(ShallowProp {
                         xhp_attr: None,
                         name: (pos, name),
                         type_: ty,
-                        visibility: modifiers.visibility,
+                        visibility,
                         flags,
                     })
                 }
@@ -4023,7 +4972,27 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             let name = prefix_colon(self.arena, name);
 
             let (type_, enum_values) = match node.hint {
-                Node::XhpEnumTy((ty, values)) => (Some(*ty), Some(values)),
+                Node::XhpEnumTy((ty, values)) => {
+                    // Mirror `make_parameter_declaration`'s soft-type
+                    // handling: under sound-dynamic mode, an inline XHP
+                    // `enum { ... }` attribute type must accept `dynamic`
+                    // values too, so wrap it in a like-type -- unless an
+                    // explicit `~enum {...}` (handled by
+                    // `make_like_type_specifier`) already did, in which case
+                    // wrapping again would produce a redundant `~~T`.
+                    let ty = if (self.opts.everything_sdt
+                        || self.opts.interpret_soft_types_as_like_types)
+                        && !matches!(**ty, Ty(_, Ty_::Tlike(_)))
+                    {
+                        self.alloc(Ty(
+                            self.alloc(Reason::hint(self.get_pos(node.hint))),
+                            Ty_::Tlike(*ty),
+                        ))
+                    } else {
+                        *ty
+                    };
+                    (Some(ty), Some(values))
+                }
                 _ => (self.node_to_ty(node.hint), None),
             };
             if let Some(enum_values) = enum_values {
@@ -4034,6 +5003,14 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                 type_.and_then(|x| match x {
                     // already nullable
                     Ty(_, Ty_::Toption(_)) | Ty(_, Ty_::Tmixed) => type_,
+                    // a soft/enum-sdt type: nullable-wrap the inner type and
+                    // keep the like-type on the outside, so this becomes
+                    // `~?T` rather than `?~T`.
+                    Ty(reason, Ty_::Tlike(inner)) => {
+                        let inner =
+                            self.node_to_ty(self.hint_ty(inner.get_pos()?, Ty_::Toption(inner)))?;
+                        Some(self.alloc(Ty(reason, Ty_::Tlike(inner))))
+                    }
                     // make nullable
                     _ => self.node_to_ty(self.hint_ty(x.get_pos()?, Ty_::Toption(x))),
                 })
@@ -4043,6 +5020,10 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
 
             let mut flags = PropFlags::empty();
             flags.set(PropFlags::NEEDS_INIT, node.needs_init);
+            // Unlike properties and methods, individual XHP attributes have
+            // no `<<...>>` user-attribute list in the grammar (`make_xhp_class_attribute`
+            // only takes a type, name, initializer, and tag), so there's no
+            // `__Internal` to promote here; they stay `Public`.
             Some(ShallowProp {
                 name: (pos, name),
                 visibility: aast::Visibility::Public,
@@ -4080,18 +5061,6 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         xhp_enum_values: Self::R,
         right_brace: Self::R,
     ) -> Self::R {
-        // Infer the type hint from the first value.
-        // TODO: T88207956 consider all the values.
-        let ty = xhp_enum_values
-            .iter()
-            .next()
-            .and_then(|node| self.node_to_ty(*node))
-            .and_then(|node_ty| {
-                let pos = self.merge_positions(enum_keyword, right_brace);
-                let ty_ = node_ty.1;
-                Some(self.alloc(Ty(self.alloc(Reason::hint(pos)), ty_)))
-            });
-
         let mut values = Vec::new_in(self.arena);
         for node in xhp_enum_values.iter() {
             // XHP enum values may only be string or int literals.
@@ -4107,11 +5076,31 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                 _ => {}
             };
         }
+        let values = values.into_bump_slice();
 
-        match ty {
-            Some(ty) => Node::XhpEnumTy(self.alloc((&ty, values.into_bump_slice()))),
-            None => Node::Ignored(SK::XHPEnumType),
+        if values.is_empty() {
+            return Node::Ignored(SK::XHPEnumType);
         }
+
+        // Infer the attribute's type from the literal set as a whole: all
+        // ints -> int, all strings -> string, a mix of both -> a union of
+        // the two, matching what the literals can actually hold rather than
+        // widening to their common supertype.
+        let ty_ = if values.iter().all(|v| matches!(v, XhpEnumValue::XEVInt(_))) {
+            Ty_::Tprim(self.arena.alloc(aast::Tprim::Tint))
+        } else if values.iter().all(|v| matches!(v, XhpEnumValue::XEVString(_))) {
+            Ty_::Tprim(self.arena.alloc(aast::Tprim::Tstring))
+        } else {
+            let reason = self.alloc(Reason::hint(self.merge_positions(enum_keyword, right_brace)));
+            let int_ty = self.alloc(Ty(reason, Ty_::Tprim(self.arena.alloc(aast::Tprim::Tint))));
+            let string_ty =
+                self.alloc(Ty(reason, Ty_::Tprim(self.arena.alloc(aast::Tprim::Tstring))));
+            Ty_::Tunion(self.intern_targs(&[int_ty, string_ty]))
+        };
+        let pos = self.merge_positions(enum_keyword, right_brace);
+        let ty = self.alloc(Ty(self.alloc(Reason::hint(pos)), ty_));
+
+        Node::XhpEnumTy(self.alloc((&ty, values)))
     }
 
     fn make_xhp_class_attribute(
@@ -4259,6 +5248,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             &Node::Const(const_) => Some(const_),
             _ => None,
         }));
+        let class_attributes = self.to_attributes(attributes);
         let mut user_attributes = Vec::with_capacity_in(attributes.len(), self.arena);
         for attribute in attributes.iter() {
             match attribute {
@@ -4300,7 +5290,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             is_xhp: false,
             has_xhp_keyword: false,
             kind: ClassishKind::Cenum,
-            module: &None, // TODO: grab module from attributes
+            module: self.alloc(self.resolve_module(class_attributes.module)),
             name: id.into(),
             tparams: &[],
             where_constraints: &[],
@@ -4362,6 +5352,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
                     .infer_const(name, value)
                     .unwrap_or_else(|| self.tany_with_pos(id.0)),
                 refs,
+                internal: false,
             }),
         )
     }
@@ -4434,6 +5425,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         let extends = extends.into_bump_slice();
         let includes = &extends[1..];
 
+        let class_attributes = self.to_attributes(attributes);
         let mut user_attributes = Vec::with_capacity_in(attributes.len() + 1, self.arena);
         for attribute in attributes.iter() {
             match attribute {
@@ -4444,6 +5436,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         user_attributes.push(self.alloc(shallow_decl_defs::UserAttribute {
             name: (name.0, "__EnumClass"),
             classname_params: &[],
+            args: &[],
         }));
         // Match ordering of attributes produced by the OCaml decl parser (even
         // though it's the reverse of the syntactic ordering).
@@ -4456,7 +5449,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             is_xhp: false,
             has_xhp_keyword: false,
             kind: class_kind,
-            module: &None, // TODO: grab module from attributes
+            module: self.alloc(self.resolve_module(class_attributes.module)),
             name: name.into(),
             tparams: &[],
             where_constraints: &[],
@@ -4498,7 +5491,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         modifiers: Self::R,
         type_: Self::R,
         name: Self::R,
-        _initializer: Self::R,
+        initializer: Self::R,
         _semicolon: Self::R,
     ) -> Self::R {
         let refs = self.stop_accumulating_const_refs();
@@ -4510,9 +5503,11 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         let has_abstract_keyword = modifiers
             .iter()
             .any(|node| node.is_token(TokenKind::Abstract));
+        let internal = modifiers
+            .iter()
+            .any(|node| node.is_token(TokenKind::Internal));
         let abstract_ = if has_abstract_keyword {
-            /* default values not allowed atm */
-            ClassConstKind::CCAbstract(false)
+            ClassConstKind::CCAbstract(!initializer.is_ignored())
         } else {
             ClassConstKind::CCConcrete
         };
@@ -4535,6 +5530,7 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             name: name.into(),
             type_,
             refs,
+            internal,
         }))
     }
 
@@ -4821,10 +5817,13 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
             &[]
         };
 
+        let args = self.slice(args.iter().filter_map(|&node| self.node_to_expr(node)));
+
         Node::Attribute(self.alloc(UserAttributeNode {
             name,
             classname_params,
             string_literal_params,
+            args,
         }))
     }
 
@@ -4867,8 +5866,23 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         self.hint_ty(pos, Ty_::Toption(ty))
     }
 
+    // `~` has its own grammar production in type position (this function),
+    // distinct from the `Tilde` prefix-unary-expression token handled by
+    // `make_prefix_unary_expression` (which yields `Uop::Utild`). The parser
+    // picks between them structurally -- by grammar production, not by
+    // probing whether the operand happens to parse as a type -- so the two
+    // meanings of `~` never need to be disambiguated dynamically here.
     fn make_like_type_specifier(&mut self, tilde: Self::R, hint: Self::R) -> Self::R {
         let pos = self.merge_positions(tilde, hint);
+        // `~enum {...}` on an XHP attribute: node_to_ty doesn't know about
+        // Node::XhpEnumTy, and flattening it to a plain Node::Ty here would
+        // throw away the enum's literal value set, which downstream callers
+        // (e.g. make_xhp_class_attribute_declaration) still need. Wrap the
+        // inner type in Tlike and keep it tagged as an XhpEnumTy instead.
+        if let Node::XhpEnumTy(&(ty, values)) = hint {
+            let ty = self.alloc(Ty(self.alloc(Reason::hint(pos)), Ty_::Tlike(ty)));
+            return Node::XhpEnumTy(self.alloc((&ty, values)));
+        }
         let ty = match self.node_to_ty(hint) {
             Some(ty) => ty,
             None => return Node::Ignored(SK::LikeTypeSpecifier),
@@ -5261,6 +6275,13 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         Node::Ignored(SK::SubscriptExpression)
     }
 
+    // `Foo->bar` (object property access) -- note this is *not* the
+    // production for `Foo::Bar` constant/enum-class-member access, which is
+    // `make_scope_resolution_expression` above and already resolves to a
+    // `ClassConst` expression. Property access can't contribute a decl-time
+    // type the same way: unlike a class constant, an instance property read
+    // depends on the receiver's runtime type, so there's no statically
+    // resolvable value here to surface onto a const initializer.
     fn make_member_selection_expression(
         &mut self,
         _object: Self::R,
@@ -5287,15 +6308,55 @@ impl<'a, 'text, S: SourceTextAllocator<'text, 'a>>
         Node::Ignored(SK::SafeMemberSelectionExpression)
     }
 
+    // The call as a whole still can't carry a decl-time type: the parser's
+    // own disambiguation logic trusts `Node::is_function_call_expression`
+    // to mean "this node is tagged `Ignored(SK::FunctionCallExpression)`", so
+    // returning anything else here would break that check for every call,
+    // not just enum-class-label ones. But a reference the call is *built
+    // from* can still be registered as a side effect, the same way a bare
+    // expression would be -- mirroring `make_scope_resolution_expression`,
+    // which resolves `Foo::Bar` into an unresolved `ClassConst` reference
+    // without reading `Foo`'s own decl (typechecking resolves it later); we
+    // do the same here for both halves `Foo#Bar(...)` can appear as.
     fn make_function_call_expression(
         &mut self,
-        _receiver: Self::R,
+        receiver: Self::R,
         _type_args: Self::R,
-        _enum_class_label: Self::R,
+        enum_class_label: Self::R,
         _left_paren: Self::R,
         _argument_list: Self::R,
         _right_paren: Self::R,
     ) -> Self::R {
+        // `Foo::bar(...)`: the receiver was already resolved to `Foo::bar` by
+        // `make_scope_resolution_expression`; register that reference so it
+        // isn't silently dropped when the call discards its receiver.
+        if let Node::Expr(aast::Expr(_, _, aast::Expr_::ClassConst(&(class_id, const_name)))) =
+            receiver
+        {
+            self.accumulate_const_ref(class_id, &Id(const_name.0, const_name.1));
+        }
+        // `Foo#Bar(...)`: the enum-class-label form. `enum_class_label` has
+        // no dedicated production in this file, so whether its name survives
+        // here depends on how the parser built it; `expect_name` degrades to
+        // `None` gracefully (no panic, no guess) if it didn't. When both the
+        // receiver and the label resolve to plain names, register the same
+        // kind of reference as `Foo::Bar` -- a label is fundamentally a
+        // reference to a member of the named class, so it folds into the
+        // same `ClassConstRef`/circularity tracking.
+        if let (Some(class_name), Some(label_name)) =
+            (self.expect_name(receiver), self.expect_name(enum_class_label))
+        {
+            let class_name = self.elaborate_id(class_name);
+            let class_id = self.alloc(aast::ClassId(
+                (),
+                class_name.0,
+                match receiver {
+                    Node::Name(("self", _)) => aast::ClassId_::CIself,
+                    _ => aast::ClassId_::CI(self.alloc(class_name)),
+                },
+            ));
+            self.accumulate_const_ref(class_id, &label_name);
+        }
         Node::Ignored(SK::FunctionCallExpression)
     }
 
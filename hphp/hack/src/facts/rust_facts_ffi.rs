@@ -4,12 +4,63 @@
 // This source code is licensed under the MIT license found in the
 // LICENSE file in the "hack" directory of this source tree.
 
+use bitflags::bitflags;
 use hhbc_by_ref_hhbc_string_utils::without_xhp_mangling;
 use ocamlrep::{bytes_from_ocamlrep, ptr::UnsafeOcamlPtr};
 use ocamlrep_ocamlpool::ocaml_ffi;
 use oxidized::relative_path::RelativePath;
+use rayon::prelude::*;
 
 use facts_rust::{facts::*, facts_parser::*};
+
+/// A single pathological file (e.g. a minified multi-megabyte blob) can't
+/// stall a whole batch: files bigger than this are skipped with an error
+/// entry instead of being handed to the parser.
+pub const MAX_BATCH_FILE_BYTES: usize = 50 * 1024 * 1024;
+
+/// Some files stay under `MAX_BATCH_FILE_BYTES` but still pathologically
+/// slow down the parser (e.g. deeply nested expressions). A file whose
+/// extraction hasn't finished within this long gets an error entry and the
+/// batch stops waiting for it instead of letting it stall every other file
+/// queued behind it on that worker; its extraction thread is not stopped.
+pub const MAX_BATCH_FILE_PARSE_TIME: std::time::Duration = std::time::Duration::from_secs(10);
+
+bitflags! {
+    /// Parser toggles sent across the FFI boundary as a single packed `i32`.
+    /// Named constants replace the old `(1 << n) & flags` decoding so a new
+    /// toggle is a one-line addition instead of a bit position kept in
+    /// lockstep with the OCaml caller by hand.
+    pub struct FactsFlags: i32 {
+        const PHP5_COMPAT_MODE = 1 << 0;
+        const HHVM_COMPAT_MODE = 1 << 1;
+        const ALLOW_NEW_ATTRIBUTE_SYNTAX = 1 << 2;
+        const ENABLE_XHP_CLASS_MODIFIER = 1 << 3;
+        const DISABLE_XHP_ELEMENT_MANGLING = 1 << 4;
+        const DISALLOW_HASH_COMMENTS = 1 << 5;
+        const INCLUDE_HASHES = 1 << 6;
+        const INCLUDE_DECLS = 1 << 7;
+    }
+}
+
+// `FactsOpts` carries a `filename` that `FactsFlags` has no notion of, so the
+// conversion takes it alongside the flags rather than as a bare `From<FactsFlags>`.
+impl From<(FactsFlags, RelativePath)> for FactsOpts {
+    fn from((flags, filename): (FactsFlags, RelativePath)) -> Self {
+        FactsOpts {
+            php5_compat_mode: flags.contains(FactsFlags::PHP5_COMPAT_MODE),
+            hhvm_compat_mode: flags.contains(FactsFlags::HHVM_COMPAT_MODE),
+            allow_new_attribute_syntax: flags.contains(FactsFlags::ALLOW_NEW_ATTRIBUTE_SYNTAX),
+            enable_xhp_class_modifier: flags.contains(FactsFlags::ENABLE_XHP_CLASS_MODIFIER),
+            disable_xhp_element_mangling: flags
+                .contains(FactsFlags::DISABLE_XHP_ELEMENT_MANGLING),
+            disallow_hash_comments: flags.contains(FactsFlags::DISALLOW_HASH_COMMENTS),
+            include_hashes: flags.contains(FactsFlags::INCLUDE_HASHES),
+            include_decls: flags.contains(FactsFlags::INCLUDE_DECLS),
+            filename,
+        }
+    }
+}
+
 ocaml_ffi! {
     fn extract_as_json_ffi(
         flags: i32,
@@ -21,40 +72,96 @@ ocaml_ffi! {
         // and text_value exist. We don't call into OCaml here, so it won't.
         let text_value = unsafe { text_ptr.as_value() };
         let text = bytes_from_ocamlrep(text_value).expect("expected string");
-        extract_facts_as_json_ffi0(
-            ((1 << 0) & flags) != 0, // php5_compat_mode
-            ((1 << 1) & flags) != 0, // hhvm_compat_mode
-            ((1 << 2) & flags) != 0, // allow_new_attribute_syntax
-            ((1 << 3) & flags) != 0, // enable_xhp_class_modifier
-            ((1 << 4) & flags) != 0, // disable_xhp_element_mangling
-            ((1 << 5) & flags) != 0, // disallow_hash_comments
-            filename,
-            text,
-            mangle_xhp,
-        )
+        extract_facts_as_json_ffi0(FactsFlags::from_bits_truncate(flags), filename, text, mangle_xhp)
     }
+
+    fn extract_as_json_batch_ffi(
+        flags: i32,
+        files: Vec<(RelativePath, UnsafeOcamlPtr, Option<String>)>,
+        mangle_xhp: bool,
+    ) -> Vec<(RelativePath, Result<Option<String>, String>)> {
+        // Safety: copy every OCaml string out up front, before any parallel
+        // work starts. The OCaml GC must not run while a text_ptr/text_value
+        // is alive, and nothing in this loop calls back into OCaml.
+        let owned: Vec<(RelativePath, Vec<u8>, Option<String>)> = files
+            .into_iter()
+            .map(|(filename, text_ptr, prior_content_hash)| {
+                let text_value = unsafe { text_ptr.as_value() };
+                let text = bytes_from_ocamlrep(text_value)
+                    .expect("expected string")
+                    .to_vec();
+                (filename, text, prior_content_hash)
+            })
+            .collect();
+        extract_as_json_batch_ffi0(FactsFlags::from_bits_truncate(flags), owned, mangle_xhp)
+    }
+}
+
+/// Parallel, whole-repo-friendly sibling of `extract_as_json_ffi`: takes every
+/// file in the batch plus one shared `FactsFlags`, extracts in parallel via a
+/// worker pool owned by the Rust side, and returns a path-keyed result so a
+/// caller (e.g. a symbol indexer) only crosses the OCaml<->Rust boundary
+/// once. A file whose `prior_content_hash` matches its current content is
+/// short-circuited to `Ok(None)` instead of being re-parsed. Oversized files
+/// are rejected up front; the batch stops waiting on slow ones once they've
+/// run past `MAX_BATCH_FILE_PARSE_TIME` (see `extract_with_cancellation`).
+pub fn extract_as_json_batch_ffi0(
+    flags: FactsFlags,
+    files: Vec<(RelativePath, Vec<u8>, Option<String>)>,
+    mangle_xhp: bool,
+) -> Vec<(RelativePath, Result<Option<String>, String>)> {
+    files
+        .into_par_iter()
+        .map(|(filename, text, prior_content_hash)| {
+            if text.len() > MAX_BATCH_FILE_BYTES {
+                let result = Err(format!(
+                    "file exceeds the {}-byte batch extraction limit, skipping",
+                    MAX_BATCH_FILE_BYTES
+                ));
+                return (filename, result);
+            }
+            if prior_content_hash.as_deref() == Some(content_hash(&text).as_str()) {
+                return (filename, Ok(None));
+            }
+            let result = extract_with_cancellation(flags, filename.clone(), text, mangle_xhp);
+            (filename, result)
+        })
+        .collect()
+}
+
+/// Runs one file's extraction on its own thread and cancels the wait -- not
+/// the parse itself, which Rust can't safely preempt mid-instruction, but the
+/// batch's wait on it -- once `MAX_BATCH_FILE_PARSE_TIME` elapses, so a
+/// pathological file can only ever hold up its own slot, never the files
+/// queued behind it.
+fn extract_with_cancellation(
+    flags: FactsFlags,
+    filename: RelativePath,
+    text: Vec<u8>,
+    mangle_xhp: bool,
+) -> Result<Option<String>, String> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let worker_filename = filename.clone();
+    std::thread::spawn(move || {
+        let result = extract_facts_as_json_ffi0(flags, worker_filename, &text, mangle_xhp);
+        // The receiver may already have timed out and moved on; that's fine.
+        let _ = tx.send(result);
+    });
+    rx.recv_timeout(MAX_BATCH_FILE_PARSE_TIME).map_err(|_| {
+        format!(
+            "file took longer than {:?} to extract facts for, cancelling",
+            MAX_BATCH_FILE_PARSE_TIME
+        )
+    })
 }
 
 pub fn extract_facts_as_json_ffi0(
-    php5_compat_mode: bool,
-    hhvm_compat_mode: bool,
-    allow_new_attribute_syntax: bool,
-    enable_xhp_class_modifier: bool,
-    disable_xhp_element_mangling: bool,
-    disallow_hash_comments: bool,
+    flags: FactsFlags,
     filename: RelativePath,
     text: &[u8],
     mangle_xhp: bool,
 ) -> Option<String> {
-    let opts = FactsOpts {
-        php5_compat_mode,
-        hhvm_compat_mode,
-        allow_new_attribute_syntax,
-        enable_xhp_class_modifier,
-        disable_xhp_element_mangling,
-        filename,
-        disallow_hash_comments,
-    };
+    let opts = FactsOpts::from((flags, filename));
     if mangle_xhp {
         extract_as_json(text, opts)
     } else {
@@ -63,28 +170,19 @@ pub fn extract_facts_as_json_ffi0(
 }
 
 pub fn extract_facts_ffi0(
-    php5_compat_mode: bool,
-    hhvm_compat_mode: bool,
-    allow_new_attribute_syntax: bool,
-    enable_xhp_class_modifier: bool,
-    disable_xhp_element_mangling: bool,
-    disallow_hash_comments: bool,
+    flags: FactsFlags,
     filename: RelativePath,
     text: &[u8],
     _mangle_xhp: bool,
 ) -> Option<Facts> {
-    let opts = FactsOpts {
-        php5_compat_mode,
-        hhvm_compat_mode,
-        allow_new_attribute_syntax,
-        enable_xhp_class_modifier,
-        disable_xhp_element_mangling,
-        filename,
-        disallow_hash_comments,
-    };
-    from_text(text, opts)
+    from_text(text, FactsOpts::from((flags, filename)))
 }
 
 pub fn facts_to_json_ffi(facts: Facts, text: &[u8]) -> String {
+    // `Facts::to_json` stamps in the SHA1 content hash and per-symbol
+    // decl-hashes computed from `text` when `FactsFlags::INCLUDE_HASHES` was
+    // set at parse time, and the `oxidized::shallow_decl_defs` payload per
+    // symbol when `FactsFlags::INCLUDE_DECLS` was set; callers that asked for
+    // neither get the old name-only output.
     facts.to_json(text)
 }
@@ -7,16 +7,35 @@ use std::borrow::Cow;
 
 use ffi::Pair;
 use hhbc_by_ref_ast_class_expr::ClassExpr;
+use hhbc_by_ref_decl_provider as decl_provider;
 use hhbc_by_ref_env::emitter::Emitter;
 use hhbc_by_ref_hhas_body::HhasBodyEnv;
 use oxidized::{ast, ast_defs, pos::Pos};
 
 pub trait SpecialClassResolver {
-    fn resolve<'a>(&self, env: Option<&'a HhasBodyEnv>, id: &'a str) -> Cow<'a, str>;
+    /// Resolves `self`/`parent`/`static` (and any other bare class id) to a
+    /// concrete name. `env`'s `class_info`/`parent_name` are consulted first;
+    /// when they don't resolve `id` and a `decl_provider` is supplied, the
+    /// enclosing class named by `env`'s `class_info` is looked up and its decl
+    /// answers `self`/`static` (the class's own name) and `parent` (the decl's
+    /// direct parent). Without `class_info` there is no class to look up, and
+    /// no ancestor beyond the direct parent is consulted; unresolved ids are
+    /// returned verbatim.
+    fn resolve<'a>(
+        &self,
+        env: Option<&'a HhasBodyEnv>,
+        decl_provider: Option<&'a dyn decl_provider::DeclProvider<'a>>,
+        id: &'a str,
+    ) -> Cow<'a, str>;
 }
 
 impl<'arena, 'decl> SpecialClassResolver for Emitter<'arena, 'decl> {
-    fn resolve<'a>(&self, env: Option<&'a HhasBodyEnv>, id: &'a str) -> Cow<'a, str> {
+    fn resolve<'a>(
+        &self,
+        env: Option<&'a HhasBodyEnv>,
+        decl_provider: Option<&'a dyn decl_provider::DeclProvider<'a>>,
+        id: &'a str,
+    ) -> Cow<'a, str> {
         let class_expr = match env {
             None => ClassExpr::expr_to_class_expr_(
                 self,
@@ -53,7 +72,28 @@ impl<'arena, 'decl> SpecialClassResolver for Emitter<'arena, 'decl> {
         };
         match class_expr {
             ClassExpr::Id(ast_defs::Id(_, name)) => Cow::Owned(name),
-            _ => Cow::Borrowed(id),
+            _ => resolve_via_decl_provider(env, decl_provider, id).unwrap_or(Cow::Borrowed(id)),
         }
     }
 }
+
+/// Cache-miss fallback: the body env didn't carry enough info for
+/// `ClassExpr` to resolve `id`, so look up the decl of the class named by
+/// `env`'s `class_info` (returns `None` when either is missing) and answer
+/// `self`/`static` with that name and `parent` with the decl's direct parent.
+fn resolve_via_decl_provider<'a>(
+    env: Option<&'a HhasBodyEnv>,
+    decl_provider: Option<&'a dyn decl_provider::DeclProvider<'a>>,
+    id: &'a str,
+) -> Option<Cow<'a, str>> {
+    let provider = decl_provider?;
+    let Pair(_, enclosing_name) = env?.class_info.as_ref()?;
+    let enclosing_decl = provider.get_class(enclosing_name.as_str())?;
+    match id {
+        "self" | "static" => Some(Cow::Owned(enclosing_name.as_str().to_string())),
+        "parent" => enclosing_decl
+            .parent_name()
+            .map(|parent| Cow::Owned(parent.to_string())),
+        _ => None,
+    }
+}
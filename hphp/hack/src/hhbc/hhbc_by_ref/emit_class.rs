@@ -4,6 +4,7 @@
 // LICENSE file in the "hack" directory of this source tree.
 
 use ffi::{Maybe, Maybe::*, Pair, Slice, Str};
+use hhbc_by_ref_decl_provider as decl_provider;
 use hhbc_by_ref_emit_attribute as emit_attribute;
 use hhbc_by_ref_emit_body as emit_body;
 use hhbc_by_ref_emit_expression as emit_expression;
@@ -11,17 +12,17 @@ use hhbc_by_ref_emit_fatal as emit_fatal;
 use hhbc_by_ref_emit_memoize_method as emit_memoize_method;
 use hhbc_by_ref_emit_method as emit_method;
 use hhbc_by_ref_emit_pos as emit_pos;
-use hhbc_by_ref_emit_property as emit_property;
+use hhbc_by_ref_emit_property::{self as emit_property, PropAndInit};
 use hhbc_by_ref_emit_symbol_refs as emit_symbol_refs;
 use hhbc_by_ref_emit_type_constant as emit_type_constant;
 use hhbc_by_ref_emit_type_hint as emit_type_hint;
 use hhbc_by_ref_emit_xhp as emit_xhp;
 use hhbc_by_ref_env::{emitter::Emitter, Env};
 use hhbc_by_ref_hhas_attribute as hhas_attribute;
-use hhbc_by_ref_hhas_class::{HhasClass, HhasClassFlags, TraitReqKind};
+use hhbc_by_ref_hhas_class::{HhasClass, TraitReqKind};
 use hhbc_by_ref_hhas_coeffects::{HhasCoeffects, HhasCtxConstant};
 use hhbc_by_ref_hhas_constant::{self as hhas_constant, HhasConstant};
-use hhbc_by_ref_hhas_method::{HhasMethod, HhasMethodFlags};
+use hhbc_by_ref_hhas_method::HhasMethod;
 use hhbc_by_ref_hhas_param::HhasParam;
 use hhbc_by_ref_hhas_pos::HhasSpan;
 use hhbc_by_ref_hhas_property::HhasProperty;
@@ -35,6 +36,7 @@ use hhbc_by_ref_hhbc_id::class::ClassType;
 use hhbc_by_ref_hhbc_id::r#const;
 use hhbc_by_ref_hhbc_id::{self as hhbc_id, class, method, prop, Id};
 use hhbc_by_ref_hhbc_string_utils as string_utils;
+use hhvm_types_ffi::ffi::Attr;
 use hhbc_by_ref_instruction_sequence::{instr, InstrSeq, Result};
 use hhbc_by_ref_label as label;
 use hhbc_by_ref_local::Local;
@@ -49,6 +51,29 @@ use oxidized::{
 
 use std::collections::BTreeMap;
 
+mod error;
+use error::Error;
+
+/// Bridges the structured `error::Result` used by the user-fatal/invariant
+/// checks in this module back onto the `instruction_sequence::Result` the
+/// rest of `emit_class` still returns: `Fatal` becomes the matching HHAS
+/// fatal unit, `Unrecoverable` aborts compilation outright.
+fn adapt<T>(result: error::Result<T>) -> Result<T> {
+    result.map_err(|e| match e {
+        Error::Fatal {
+            pos,
+            kind: error::FatalKind::Parse,
+            msg,
+        } => emit_fatal::raise_fatal_parse(&pos, msg),
+        Error::Fatal {
+            pos,
+            kind: error::FatalKind::Runtime,
+            msg,
+        } => emit_fatal::raise_fatal_runtime(&pos, msg),
+        Error::Unrecoverable(msg) => panic!("emit_class: unrecoverable compiler error: {}", msg),
+    })
+}
+
 fn add_symbol_refs<'arena, 'decl>(
     alloc: &'arena bumpalo::Bump,
     emitter: &mut Emitter<'arena, 'decl>,
@@ -70,6 +95,16 @@ fn add_symbol_refs<'arena, 'decl>(
         .for_each(|(x, _)| emit_symbol_refs::add_class(alloc, emitter, *x));
 }
 
+fn from_module_name<'a>(ast_class: &'a ast::Class_) -> Option<&'a str> {
+    ast_class
+        .user_attributes
+        .iter()
+        .find(|attr| attr.name.1 == "__Module")
+        .and_then(|attr| attr.params.first())
+        .and_then(|e| e.2.as_string())
+        .map(|s| s.as_str())
+}
+
 fn make_86method<'a, 'arena, 'decl>(
     alloc: &'arena bumpalo::Bump,
     emitter: &mut Emitter<'arena, 'decl>,
@@ -78,16 +113,24 @@ fn make_86method<'a, 'arena, 'decl>(
     is_static: bool,
     visibility: Visibility,
     is_abstract: bool,
+    is_internal: bool,
     span: HhasSpan,
     instrs: InstrSeq<'arena>,
 ) -> Result<HhasMethod<'arena>> {
     // TODO: move this. We just know that there are no iterators in 86methods
     emitter.iterator_mut().reset();
 
-    let mut flags = HhasMethodFlags::empty();
-    flags.set(HhasMethodFlags::NO_INJECTION, true);
-    flags.set(HhasMethodFlags::IS_ABSTRACT, is_abstract);
-    flags.set(HhasMethodFlags::IS_STATIC, is_static);
+    let mut attrs = Attr::empty();
+    attrs.set(Attr::AttrNoInjection, true);
+    attrs.set(Attr::AttrAbstract, is_abstract);
+    attrs.set(Attr::AttrStatic, is_static);
+    attrs.set(Attr::AttrPrivate, visibility == Visibility::Private);
+    attrs.set(Attr::AttrProtected, visibility == Visibility::Protected);
+    attrs.set(Attr::AttrPublic, visibility == Visibility::Public);
+    // A module-internal class's generated accessors (86pinit/86sinit/86cinit,
+    // the reified-init shim, ...) must carry the same module membership as
+    // the class itself so the runtime enforces the boundary consistently.
+    attrs.set(Attr::AttrInternal, is_internal);
 
     let attributes = vec![];
     let coeffects = HhasCoeffects::pure(alloc);
@@ -119,7 +162,7 @@ fn make_86method<'a, 'arena, 'decl>(
         body,
         attributes: Slice::fill_iter(alloc, attributes.into_iter()),
         name,
-        flags,
+        attrs,
         span,
         coeffects,
         visibility: Visibility::from(visibility),
@@ -177,6 +220,13 @@ fn from_includes<'arena>(
         .collect()
 }
 
+// Left on `instruction_sequence::Result` rather than migrated to
+// `error::Result`: its only fallible call, `emit_type_constant::hint_to_type_constant`,
+// already returns a fully-formed `instruction_sequence::Error` (Parse vs.
+// Runtime baked in) from an out-of-tree crate this repo doesn't vendor, and
+// there's no lossless way back from that into `error::Error` -- re-wrapping
+// it as `Unrecoverable` would turn a legal-Hack-program parse fatal into a
+// hard compiler panic, which is a correctness regression, not a migration.
 fn from_type_constant<'a, 'arena, 'decl>(
     alloc: &'arena bumpalo::Bump,
     emitter: &mut Emitter<'arena, 'decl>,
@@ -258,7 +308,7 @@ fn from_class_elt_classvars<'a, 'arena, 'decl>(
     ast_class: &'a ast::Class_,
     class_is_const: bool,
     tparams: &[&str],
-) -> Result<Vec<HhasProperty<'arena>>> {
+) -> Result<Vec<PropAndInit<'arena>>> {
     // TODO: we need to emit doc comments for each property,
     // not one per all properties on the same line
     // The doc comment is only for the first name in the list.
@@ -318,17 +368,27 @@ fn from_class_elt_constants<'a, 'arena, 'decl>(
 fn from_class_elt_requirements<'a, 'arena>(
     alloc: &'arena bumpalo::Bump,
     class_: &'a ast::Class_,
-) -> Vec<(hhbc_id::class::ClassType<'arena>, TraitReqKind)> {
+) -> Result<Vec<(hhbc_id::class::ClassType<'arena>, TraitReqKind)>> {
+    use ast::RequireKind;
+    let is_trait = class_.kind == ast::ClassishKind::Ctrait;
     class_
         .reqs
         .iter()
-        .map(|(h, is_extends)| {
-            let kind = if *is_extends {
-                TraitReqKind::MustExtend
-            } else {
-                TraitReqKind::MustImplement
+        .map(|(h, req_kind)| {
+            let kind = match req_kind {
+                RequireKind::RequireExtends => TraitReqKind::MustExtend,
+                RequireKind::RequireImplements => TraitReqKind::MustImplement,
+                RequireKind::RequireClass => {
+                    if !is_trait {
+                        return Err(emit_fatal::raise_fatal_parse(
+                            &h.0,
+                            "'require class' constraints may only be used on traits",
+                        ));
+                    }
+                    TraitReqKind::MustBeClass
+                }
             };
-            (emit_type_hint::hint_to_class(alloc, h), kind)
+            Ok((emit_type_hint::hint_to_class(alloc, h), kind))
         })
         .collect()
 }
@@ -337,13 +397,15 @@ fn from_enum_type<'arena>(
     alloc: &'arena bumpalo::Bump,
     opt: Option<&ast::Enum_>,
 ) -> Result<Option<HhasTypeInfo<'arena>>> {
-    use hhbc_by_ref_hhas_type::constraint::*;
+    use hhbc_by_ref_hhas_type::constraint::Constraint;
+    use hhvm_types_ffi::ffi::TypeConstraintFlags;
     opt.map(|e| {
         let type_info_user_type = Just(Str::new_str(
             alloc,
             emit_type_hint::fmt_hint(alloc, &[], true, &e.base)?,
         ));
-        let type_info_type_constraint = Constraint::make(Nothing, ConstraintFlags::EXTENDED_HINT);
+        let type_info_type_constraint =
+            Constraint::make(Nothing, TypeConstraintFlags::ExtendedHint);
         Ok(HhasTypeInfo::make(
             type_info_user_type,
             type_info_type_constraint,
@@ -352,7 +414,10 @@ fn from_enum_type<'arena>(
     .transpose()
 }
 
-fn validate_class_name(ns: &namespace_env::Env, ast::Id(p, class_name): &ast::Id) -> Result<()> {
+fn validate_class_name(
+    ns: &namespace_env::Env,
+    ast::Id(p, class_name): &ast::Id,
+) -> error::Result<()> {
     let is_global_namespace = |ns: &namespace_env::Env| ns.name.is_none();
     let is_hh_namespace = |ns: &namespace_env::Env| {
         ns.name
@@ -373,7 +438,7 @@ fn validate_class_name(ns: &namespace_env::Env, ast::Id(p, class_name): &ast::Id
         && (is_reserved_global_name
             || (check_hh_name && special_names::typehints::is_reserved_hh_name(&lower_name)));
     if name_is_reserved {
-        Err(emit_fatal::raise_fatal_parse(
+        Err(Error::fatal_parse(
             p,
             format!(
                 "Cannot use '{}' as class name as it is reserved",
@@ -497,8 +562,10 @@ fn emit_reified_init_method<'a, 'arena, 'decl>(
     emitter: &mut Emitter<'arena, 'decl>,
     env: &Env<'a, 'arena>,
     ast_class: &'a ast::Class_,
+    is_internal: bool,
 ) -> Result<Option<HhasMethod<'arena>>> {
-    use hhbc_by_ref_hhas_type::constraint::*;
+    use hhbc_by_ref_hhas_type::constraint::Constraint;
+    use hhvm_types_ffi::ffi::TypeConstraintFlags;
 
     let alloc = env.arena;
     let num_reified = ast_class
@@ -513,7 +580,7 @@ fn emit_reified_init_method<'a, 'arena, 'decl>(
     if num_reified == 0 && !maybe_has_reified_parents {
         Ok(None)
     } else {
-        let tc = Constraint::make(Just("HH\\varray".into()), ConstraintFlags::empty());
+        let tc = Constraint::make(Just("HH\\varray".into()), TypeConstraintFlags::NoFlags);
         let params = vec![HhasParam {
             name: Str::new_str(alloc, string_utils::reified::INIT_METH_PARAM_NAME),
             is_variadic: false,
@@ -534,67 +601,503 @@ fn emit_reified_init_method<'a, 'arena, 'decl>(
             false, // is_static
             Visibility::Protected,
             false, // is_abstract
+            is_internal,
             HhasSpan::from_pos(&ast_class.span),
             instrs,
         )?))
     }
 }
 
-fn make_init_method<'a, 'arena, 'decl, F>(
+/// Walks the `extends` chain of `name` via the emitter's `DeclProvider`,
+/// looking for an ancestor that declares a reified (or soft-reified) type
+/// parameter. A missing/unresolved decl along the way is treated
+/// conservatively as "may be reified", and a cyclic `extends` edge
+/// terminates the walk the same way rather than looping forever.
+fn ancestor_chain_may_be_reified<'decl>(
+    decl_provider: Option<&'decl dyn decl_provider::DeclProvider<'decl>>,
+    name: &str,
+    seen: &mut std::collections::HashSet<std::string::String>,
+) -> bool {
+    if !seen.insert(name.to_string()) {
+        return true;
+    }
+    let provider = match decl_provider {
+        Some(p) => p,
+        None => return true,
+    };
+    match provider.get_class(name) {
+        None => true,
+        Some(decl) => {
+            let has_own_reified = decl
+                .tparams
+                .iter()
+                .any(|t| matches!(t.reified, ReifyKind::Reified | ReifyKind::SoftReified));
+            has_own_reified
+                || decl
+                    .parent_name()
+                    .map_or(false, |parent| {
+                        ancestor_chain_may_be_reified(Some(provider), parent, seen)
+                    })
+        }
+    }
+}
+
+/// `NEEDS_NO_REIFIEDINIT` is set for a class that has its own `86reifiedinit`
+/// when it has no parent, or when every ancestor resolves through the
+/// `DeclProvider` and none of them declares reified generics. The class's own
+/// type parameters are not checked here.
+fn compute_needs_no_reifiedinit<'a, 'arena, 'decl>(
+    emitter: &Emitter<'arena, 'decl>,
+    ast_class: &'a ast::Class_,
+    has_reified_init: bool,
+) -> bool {
+    if !has_reified_init {
+        return false;
+    }
+    if ast_class.extends.is_empty() {
+        return true;
+    }
+    match ast_class
+        .extends
+        .first()
+        .and_then(|h| h.1.as_happly())
+        .map(|(id, _)| id.1.as_str())
+    {
+        Some(parent_name) => !ancestor_chain_may_be_reified(
+            emitter.decl_provider(),
+            parent_name,
+            &mut std::collections::HashSet::new(),
+        ),
+        None => false,
+    }
+}
+
+fn make_init_method<'arena, 'decl>(
     alloc: &'arena bumpalo::Bump,
     emitter: &mut Emitter<'arena, 'decl>,
-    properties: &[HhasProperty<'arena>],
-    filter: F,
+    init_instrs: Vec<InstrSeq<'arena>>,
     name: &'static str,
+    is_internal: bool,
     span: HhasSpan,
-) -> Result<Option<HhasMethod<'arena>>>
-where
-    F: Fn(&HhasProperty<'arena>) -> bool,
-{
-    if properties
-        .iter()
-        .any(|p: &HhasProperty| p.initializer_instrs.is_just() && filter(p))
-    {
-        let instrs = InstrSeq::gather(
+) -> Result<Option<HhasMethod<'arena>>> {
+    if init_instrs.is_empty() {
+        return Ok(None);
+    }
+    let instrs = InstrSeq::gather(alloc, init_instrs);
+    let instrs = InstrSeq::gather(alloc, vec![instrs, instr::null(alloc), instr::retc(alloc)]);
+    Ok(Some(make_86method(
+        alloc,
+        emitter,
+        (alloc, name).into(),
+        vec![],
+        true, // is_static
+        Visibility::Private,
+        false, // is_abstract
+        is_internal,
+        span,
+        instrs,
+    )?))
+}
+
+fn validate_readonly_static_lsb<'arena>(
+    property: &HhasProperty<'arena>,
+    pos: &Pos,
+) -> error::Result<()> {
+    if property.is_readonly() {
+        Err(Error::fatal_runtime(
+            pos,
+            format!(
+                "Static property {} cannot be marked readonly",
+                property.name.unsafe_as_str()
+            ),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Consumes the properties in one pass, moving each property's owned
+/// initializer instructions into the static/instance/lsb-static bucket it
+/// belongs to instead of re-scanning (and cloning) the full vector per
+/// 86pinit/86sinit/86linit method, as `PropAndInit` used to require.
+fn make_init_methods<'arena, 'decl>(
+    alloc: &'arena bumpalo::Bump,
+    emitter: &mut Emitter<'arena, 'decl>,
+    props_and_inits: Vec<PropAndInit<'arena>>,
+    is_internal: bool,
+    pos: &Pos,
+    span: HhasSpan,
+) -> Result<(
+    Vec<HhasProperty<'arena>>,
+    Option<HhasMethod<'arena>>,
+    Option<HhasMethod<'arena>>,
+    Option<HhasMethod<'arena>>,
+)> {
+    let mut properties = Vec::with_capacity(props_and_inits.len());
+    let mut pinit_instrs = vec![];
+    let mut sinit_instrs = vec![];
+    let mut linit_instrs = vec![];
+    // `instrs` is each property's already-built initializer sequence, owned
+    // and constructed by `emit_property::from_ast`; this function can only
+    // accept or reject it wholesale; it can't retrofit which `ReadonlyOp` the
+    // write inside it already used.
+    for PropAndInit { property, init } in props_and_inits {
+        if let Some(instrs) = init {
+            if property.is_static() {
+                if property.is_lsb() {
+                    adapt(validate_readonly_static_lsb(&property, pos))?;
+                    linit_instrs.push(instrs);
+                } else {
+                    sinit_instrs.push(instrs);
+                }
+            } else {
+                pinit_instrs.push(instrs);
+            }
+        }
+        properties.push(property);
+    }
+    let pinit_method =
+        make_init_method(alloc, emitter, pinit_instrs, "86pinit", is_internal, span)?;
+    let sinit_method =
+        make_init_method(alloc, emitter, sinit_instrs, "86sinit", is_internal, span)?;
+    let linit_method =
+        make_init_method(alloc, emitter, linit_instrs, "86linit", is_internal, span)?;
+    Ok((properties, pinit_method, sinit_method, linit_method))
+}
+
+/// Classes (other than interfaces) with more late-initialized constants than
+/// this fall back to one small dedicated
+/// `86cinit$<name>` method per late-initialized constant plus a thin
+/// dispatcher, instead of materializing every initializer into a single
+/// monolithic `86cinit` string switch.
+const LAZY_CINIT_THRESHOLD: usize = 16;
+
+fn make_cinit_fatal_instrs<'arena>(
+    alloc: &'arena bumpalo::Bump,
+    default_label: label::Label,
+    pos: &Pos,
+) -> InstrSeq<'arena> {
+    InstrSeq::gather(
+        alloc,
+        vec![
+            instr::label(alloc, default_label),
+            emit_pos::emit_pos(alloc, pos),
+            instr::string(alloc, "Could not find initializer for "),
+            instr::cgetl(alloc, Local::Named(Slice::new("$constName".as_bytes()))),
+            instr::string(alloc, " in 86cinit"),
+            instr::concatn(alloc, 3),
+            instr::fatal(alloc, FatalOp::Runtime),
+        ],
+    )
+}
+
+fn make_compact_cinit_method<'arena, 'decl>(
+    alloc: &'arena bumpalo::Bump,
+    emitter: &mut Emitter<'arena, 'decl>,
+    pos: &Pos,
+    span: HhasSpan,
+    is_interface: bool,
+    is_internal: bool,
+    initialized_constants: &[(&r#const::ConstType<'arena>, label::Label, &InstrSeq<'arena>)],
+) -> Result<HhasMethod<'arena>> {
+    fn make_cinit_instrs<'arena, 'decl>(
+        alloc: &'arena bumpalo::Bump,
+        e: &mut Emitter<'arena, 'decl>,
+        default_label: label::Label,
+        pos: &Pos,
+        consts: &[(&r#const::ConstType<'arena>, label::Label, &InstrSeq<'arena>)],
+    ) -> InstrSeq<'arena> {
+        match consts {
+            [] => make_cinit_fatal_instrs(alloc, default_label, pos),
+            [(_, label, instrs), cs @ ..] => InstrSeq::gather(
+                alloc,
+                vec![
+                    instr::label(alloc, *label),
+                    InstrSeq::clone(alloc, *instrs),
+                    emit_pos::emit_pos(alloc, pos),
+                    instr::retc(alloc),
+                    make_cinit_instrs(alloc, e, default_label, pos, cs),
+                ],
+            ),
+        }
+    }
+    let default_label = emitter.label_gen_mut().next_regular();
+
+    let body_instrs = {
+        let mut cases =
+            bumpalo::collections::Vec::with_capacity_in(initialized_constants.len() + 1, alloc);
+        for (name, label, _) in initialized_constants {
+            let n: &str = alloc.alloc_str((*name).to_raw_string());
+            cases.push((n, *label))
+        }
+        cases.push((alloc.alloc_str("default"), default_label));
+        InstrSeq::gather(
             alloc,
-            properties
-                .iter()
-                .filter_map(|p| {
-                    if filter(p) {
-                        // TODO(hrust) this clone can be avoided by wrapping initializer_instrs by Rc
-                        // and also support Rc in InstrSeq
-                        std::convert::Into::<Option<_>>::into(p.initializer_instrs.as_ref())
-                            .map(|i| InstrSeq::clone(alloc, i))
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
+            vec![
+                instr::cgetl(alloc, Local::Named(Slice::new("$constName".as_bytes()))),
+                instr::sswitch(alloc, cases),
+                make_cinit_instrs(alloc, emitter, default_label, pos, initialized_constants),
+            ],
+        )
+    };
+    let instrs = emit_pos::emit_pos_then(alloc, pos, body_instrs);
+    let params = vec![HhasParam {
+        name: Str::new_str(alloc, "$constName"),
+        is_variadic: false,
+        is_inout: false,
+        is_readonly: false,
+        user_attributes: Slice::empty(),
+        type_info: Nothing,
+        default_value: Nothing,
+    }];
+
+    make_86method(
+        alloc,
+        emitter,
+        (alloc, "86cinit").into(),
+        params,
+        true, /* is_static */
+        Visibility::Private,
+        is_interface, /* is_abstract */
+        is_internal,
+        span,
+        instrs,
+    )
+}
+
+fn make_lazy_cinit_methods<'arena, 'decl>(
+    alloc: &'arena bumpalo::Bump,
+    emitter: &mut Emitter<'arena, 'decl>,
+    pos: &Pos,
+    span: HhasSpan,
+    is_internal: bool,
+    initialized_constants: &[(&r#const::ConstType<'arena>, label::Label, &InstrSeq<'arena>)],
+) -> Result<Vec<HhasMethod<'arena>>> {
+    let mut methods = Vec::with_capacity(initialized_constants.len() + 1);
+    let default_label = emitter.label_gen_mut().next_regular();
+    let mut cases =
+        bumpalo::collections::Vec::with_capacity_in(initialized_constants.len() + 1, alloc);
+    for (name, label, instrs) in initialized_constants {
+        let entry_name = format!("86cinit${}", name.to_raw_string());
+        let entry_instrs = InstrSeq::gather(
+            alloc,
+            vec![
+                InstrSeq::clone(alloc, *instrs),
+                emit_pos::emit_pos(alloc, pos),
+                instr::retc(alloc),
+            ],
         );
-        let instrs = InstrSeq::gather(alloc, vec![instrs, instr::null(alloc), instr::retc(alloc)]);
-        Ok(Some(make_86method(
+        methods.push(make_86method(
             alloc,
             emitter,
-            (alloc, name).into(),
+            (alloc, alloc.alloc_str(&entry_name) as &str).into(),
             vec![],
-            true, // is_static
+            true, /* is_static */
             Visibility::Private,
-            false, // is_abstract
+            false, /* is_abstract */
+            is_internal,
             span,
-            instrs,
-        )?))
+            entry_instrs,
+        )?);
+        let n: &str = alloc.alloc_str((*name).to_raw_string());
+        cases.push((n, *label));
+    }
+    cases.push((alloc.alloc_str("default"), default_label));
+
+    let dispatch_instrs = InstrSeq::gather(
+        alloc,
+        initialized_constants
+            .iter()
+            .map(|(name, label, _)| {
+                InstrSeq::gather(
+                    alloc,
+                    vec![
+                        instr::label(alloc, *label),
+                        instr::nulluninit(alloc),
+                        instr::nulluninit(alloc),
+                        instr::fcallclsmethodsd(
+                            alloc,
+                            FcallArgs::new(
+                                FcallFlags::default(),
+                                0,
+                                Slice::empty(),
+                                Slice::empty(),
+                                None,
+                                0,
+                                None,
+                            ),
+                            SpecialClsRef::Self_,
+                            method::from_raw_string(
+                                alloc,
+                                alloc.alloc_str(&format!("86cinit${}", name.to_raw_string())),
+                            ),
+                        ),
+                        instr::retc(alloc),
+                    ],
+                )
+            })
+            .collect(),
+    );
+    let body_instrs = InstrSeq::gather(
+        alloc,
+        vec![
+            instr::cgetl(alloc, Local::Named(Slice::new("$constName".as_bytes()))),
+            instr::sswitch(alloc, cases),
+            dispatch_instrs,
+            make_cinit_fatal_instrs(alloc, default_label, pos),
+        ],
+    );
+    let instrs = emit_pos::emit_pos_then(alloc, pos, body_instrs);
+    let params = vec![HhasParam {
+        name: Str::new_str(alloc, "$constName"),
+        is_variadic: false,
+        is_inout: false,
+        is_readonly: false,
+        user_attributes: Slice::empty(),
+        type_info: Nothing,
+        default_value: Nothing,
+    }];
+    methods.push(make_86method(
+        alloc,
+        emitter,
+        (alloc, "86cinit").into(),
+        params,
+        true, // is_static
+        Visibility::Private,
+        false, // is_abstract
+        is_internal,
+        span,
+        instrs,
+    )?);
+    Ok(methods)
+}
+
+fn make_cinit_methods<'arena, 'decl>(
+    alloc: &'arena bumpalo::Bump,
+    emitter: &mut Emitter<'arena, 'decl>,
+    pos: &Pos,
+    span: HhasSpan,
+    is_interface: bool,
+    is_internal: bool,
+    initialized_constants: &[(&r#const::ConstType<'arena>, label::Label, &InstrSeq<'arena>)],
+) -> Result<Vec<HhasMethod<'arena>>> {
+    if initialized_constants.is_empty() {
+        Ok(vec![])
+    } else if is_interface || initialized_constants.len() <= LAZY_CINIT_THRESHOLD {
+        Ok(vec![make_compact_cinit_method(
+            alloc,
+            emitter,
+            pos,
+            span,
+            is_interface,
+            is_internal,
+            initialized_constants,
+        )?])
     } else {
-        Ok(None)
+        make_lazy_cinit_methods(alloc, emitter, pos, span, is_internal, initialized_constants)
     }
 }
 
+/// Emits a synthetic `86ctxinit` accessor that maps a context-constant name
+/// to its resolved capability set, so callers can query a class's declared
+/// contexts at runtime. Reuses the `sswitch`/name-dispatch shape already
+/// built for `86cinit`, including the "unknown name" runtime fatal.
+fn make_ctx_constants_method<'arena, 'decl>(
+    alloc: &'arena bumpalo::Bump,
+    emitter: &mut Emitter<'arena, 'decl>,
+    pos: &Pos,
+    span: HhasSpan,
+    ctx_constants: &[HhasCtxConstant<'arena>],
+) -> Result<Option<HhasMethod<'arena>>> {
+    let concrete: Vec<&HhasCtxConstant<'arena>> =
+        ctx_constants.iter().filter(|c| !c.is_abstract).collect();
+    if concrete.is_empty() {
+        return Ok(None);
+    }
+
+    let default_label = emitter.label_gen_mut().next_regular();
+    let mut cases = bumpalo::collections::Vec::with_capacity_in(concrete.len() + 1, alloc);
+    let mut bodies = vec![];
+    for ctx in &concrete {
+        let label = emitter.label_gen_mut().next_regular();
+        let name: &str = alloc.alloc_str(ctx.name.unsafe_as_str());
+        cases.push((name, label));
+        let capability_names = ctx.coeffects.1;
+        let instrs = InstrSeq::gather(
+            alloc,
+            vec![
+                instr::label(alloc, label),
+                instr::typedvalue(
+                    alloc,
+                    TypedValue::Vec(Slice::fill_iter(
+                        alloc,
+                        capability_names
+                            .as_ref()
+                            .iter()
+                            .map(|s| TypedValue::String(*s)),
+                    )),
+                ),
+                instr::retc(alloc),
+            ],
+        );
+        bodies.push(instrs);
+    }
+    cases.push((alloc.alloc_str("default"), default_label));
+
+    let not_found = InstrSeq::gather(
+        alloc,
+        vec![
+            instr::label(alloc, default_label),
+            emit_pos::emit_pos(alloc, pos),
+            instr::string(alloc, "Could not find context initializer for "),
+            instr::cgetl(alloc, Local::Named(Slice::new("$ctxName".as_bytes()))),
+            instr::string(alloc, " in 86ctxinit"),
+            instr::concatn(alloc, 3),
+            instr::fatal(alloc, FatalOp::Runtime),
+        ],
+    );
+
+    let body_instrs = InstrSeq::gather(
+        alloc,
+        vec![
+            instr::cgetl(alloc, Local::Named(Slice::new("$ctxName".as_bytes()))),
+            instr::sswitch(alloc, cases),
+            InstrSeq::gather(alloc, bodies),
+            not_found,
+        ],
+    );
+    let instrs = emit_pos::emit_pos_then(alloc, pos, body_instrs);
+    let params = vec![HhasParam {
+        name: Str::new_str(alloc, "$ctxName"),
+        is_variadic: false,
+        is_inout: false,
+        is_readonly: false,
+        user_attributes: Slice::empty(),
+        type_info: Nothing,
+        default_value: Nothing,
+    }];
+
+    Ok(Some(make_86method(
+        alloc,
+        emitter,
+        (alloc, "86ctxinit").into(),
+        params,
+        true, /* is_static */
+        Visibility::Private,
+        false, /* is_abstract */
+        false, /* is_internal: coeffect accessors are queried cross-module */
+        span,
+        instrs,
+    )?))
+}
+
 pub fn emit_class<'a, 'arena, 'decl>(
     alloc: &'arena bumpalo::Bump,
     emitter: &mut Emitter<'arena, 'decl>,
     ast_class: &'a ast::Class_,
 ) -> Result<HhasClass<'arena>> {
     let namespace = &ast_class.namespace;
-    validate_class_name(namespace, &ast_class.name)?;
+    adapt(validate_class_name(namespace, &ast_class.name))?;
     let mut env = Env::make_class_env(alloc, ast_class);
     // TODO: communicate this without looking at the name
     let is_closure = ast_class.name.1.starts_with("Closure$");
@@ -609,6 +1112,8 @@ pub fn emit_class<'a, 'arena, 'decl>(
     }
 
     let is_const = hhas_attribute::has_const(attributes.as_ref());
+    let is_internal = hhas_attribute::has_internal(attributes.as_ref());
+    let module_name = from_module_name(ast_class);
     // In the future, we intend to set class_no_dynamic_props independently from
     // class_is_const, but for now class_is_const is the only thing that turns
     // it on.
@@ -617,23 +1122,22 @@ pub fn emit_class<'a, 'arena, 'decl>(
     let is_trait = ast_class.kind == ast::ClassishKind::Ctrait;
     let is_interface = ast_class.kind == ast::ClassishKind::Cinterface;
 
-    let uses = ast_class
-        .uses
-        .iter()
-        .filter_map(|x| match x.1.as_ref() {
-            ast::Hint_::Happly(ast::Id(_, name), _) => {
-                if is_interface {
-                    Some(Err(emit_fatal::raise_fatal_parse(
-                        &x.0,
-                        "Interfaces cannot use traits",
-                    )))
-                } else {
-                    Some(Ok(name.as_str()))
+    let uses = adapt(
+        ast_class
+            .uses
+            .iter()
+            .filter_map(|x| match x.1.as_ref() {
+                ast::Hint_::Happly(ast::Id(_, name), _) => {
+                    if is_interface {
+                        Some(Err(Error::fatal_parse(&x.0, "Interfaces cannot use traits")))
+                    } else {
+                        Some(Ok(name.as_str()))
+                    }
                 }
-            }
-            _ => None,
-        })
-        .collect::<Result<Vec<_>>>()?;
+                _ => None,
+            })
+            .collect::<error::Result<Vec<_>>>(),
+    )?;
 
     let elaborate_namespace_id =
         |x: &'a ast::Id| hhbc_id::class::ClassType::from_ast_name(alloc, x.name());
@@ -766,21 +1270,14 @@ pub fn emit_class<'a, 'arena, 'decl>(
         )?)
     }
     emitter.label_gen_mut().reset();
-    let mut properties = from_class_elt_classvars(alloc, emitter, &ast_class, is_const, &tparams)?;
+    let props_and_inits =
+        from_class_elt_classvars(alloc, emitter, &ast_class, is_const, &tparams)?;
     let constants = from_class_elt_constants(emitter, &env, ast_class)?;
 
-    let requirements = from_class_elt_requirements(alloc, ast_class);
+    let requirements = from_class_elt_requirements(alloc, ast_class)?;
 
-    let pinit_filter = |p: &HhasProperty| !p.is_static();
-    let sinit_filter = |p: &HhasProperty| p.is_static() && !p.is_lsb();
-    let linit_filter = |p: &HhasProperty| p.is_static() && p.is_lsb();
-
-    let pinit_method =
-        make_init_method(alloc, emitter, &properties, &pinit_filter, "86pinit", span)?;
-    let sinit_method =
-        make_init_method(alloc, emitter, &properties, &sinit_filter, "86sinit", span)?;
-    let linit_method =
-        make_init_method(alloc, emitter, &properties, &linit_filter, "86linit", span)?;
+    let (mut properties, pinit_method, sinit_method, linit_method) =
+        make_init_methods(alloc, emitter, props_and_inits, is_internal, &ast_class.span, span)?;
 
     let initialized_constants: Vec<_> = constants
         .iter()
@@ -799,105 +1296,24 @@ pub fn emit_class<'a, 'arena, 'decl>(
             },
         )
         .collect();
-    let cinit_method = if initialized_constants.is_empty() {
-        None
-    } else {
-        fn make_cinit_instrs<'arena, 'decl>(
-            alloc: &'arena bumpalo::Bump,
-            e: &mut Emitter<'arena, 'decl>,
-            default_label: label::Label,
-            pos: &Pos,
-            consts: &[(&r#const::ConstType<'arena>, label::Label, &InstrSeq<'arena>)],
-        ) -> InstrSeq<'arena> {
-            match consts {
-                [] => InstrSeq::gather(
-                    alloc,
-                    vec![
-                        instr::label(alloc, default_label),
-                        emit_pos::emit_pos(alloc, pos),
-                        instr::string(alloc, "Could not find initializer for "),
-                        instr::cgetl(alloc, Local::Named(Slice::new("$constName".as_bytes()))),
-                        instr::string(alloc, " in 86cinit"),
-                        instr::concatn(alloc, 3),
-                        instr::fatal(alloc, FatalOp::Runtime),
-                    ],
-                ),
-                [(_, label, instrs), cs @ ..] => InstrSeq::gather(
-                    alloc,
-                    vec![
-                        instr::label(alloc, *label),
-                        InstrSeq::clone(alloc, *instrs),
-                        emit_pos::emit_pos(alloc, pos),
-                        instr::retc(alloc),
-                        make_cinit_instrs(alloc, e, default_label, pos, cs),
-                    ],
-                ),
-            }
-        }
-        let default_label = emitter.label_gen_mut().next_regular();
-
-        let body_instrs = {
-            let mut cases =
-                bumpalo::collections::Vec::with_capacity_in(initialized_constants.len() + 1, alloc);
-            for (name, label, _) in &initialized_constants {
-                let n: &str = alloc.alloc_str((*name).to_raw_string());
-                cases.push((n, *label))
-            }
-            cases.push((alloc.alloc_str("default"), default_label));
-            InstrSeq::gather(
-                alloc,
-                vec![
-                    instr::cgetl(alloc, Local::Named(Slice::new("$constName".as_bytes()))),
-                    instr::sswitch(alloc, cases),
-                    make_cinit_instrs(
-                        alloc,
-                        emitter,
-                        default_label,
-                        &ast_class.span,
-                        &initialized_constants[..],
-                    ),
-                ],
-            )
-        };
-        let instrs = emit_pos::emit_pos_then(alloc, &ast_class.span, body_instrs);
-        let params = vec![HhasParam {
-            name: Str::new_str(alloc, "$constName"),
-            is_variadic: false,
-            is_inout: false,
-            is_readonly: false,
-            user_attributes: Slice::empty(),
-            type_info: Nothing,
-            default_value: Nothing,
-        }];
-
-        Some(make_86method(
-            alloc,
-            emitter,
-            (alloc, "86cinit").into(),
-            params,
-            true, /* is_static */
-            Visibility::Private,
-            is_interface, /* is_abstract */
-            span,
-            instrs,
-        )?)
-    };
+    let cinit_methods = make_cinit_methods(
+        alloc,
+        emitter,
+        &ast_class.span,
+        span,
+        is_interface,
+        is_internal,
+        &initialized_constants[..],
+    )?;
 
     let should_emit_reified_init = !(emitter.systemlib() || is_closure || is_interface || is_trait);
     let reified_init_method = if should_emit_reified_init {
-        emit_reified_init_method(emitter, &env, ast_class)?
+        emit_reified_init_method(emitter, &env, ast_class, is_internal)?
     } else {
         None
     };
-    let needs_no_reifiedinit = reified_init_method.is_some() && ast_class.extends.is_empty();
-    additional_methods.extend(reified_init_method.into_iter());
-    additional_methods.extend(pinit_method.into_iter());
-    additional_methods.extend(sinit_method.into_iter());
-    additional_methods.extend(linit_method.into_iter());
-    additional_methods.extend(cinit_method.into_iter());
-
-    let mut methods = emit_method::from_asts(alloc, emitter, ast_class, &ast_class.methods)?;
-    methods.extend(additional_methods.into_iter());
+    let needs_no_reifiedinit =
+        compute_needs_no_reifiedinit(emitter, ast_class, reified_init_method.is_some());
     let (ctxconsts, tconsts): (Vec<_>, Vec<_>) =
         ast_class.typeconsts.iter().partition(|x| x.is_ctx);
     let type_constants = tconsts
@@ -908,6 +1324,18 @@ pub fn emit_class<'a, 'arena, 'decl>(
         .iter()
         .map(|x| from_ctx_constant(alloc, x))
         .collect::<Result<Vec<HhasCtxConstant>>>()?;
+    let ctx_constants_method =
+        make_ctx_constants_method(alloc, emitter, &ast_class.span, span, &ctx_constants)?;
+
+    additional_methods.extend(reified_init_method.into_iter());
+    additional_methods.extend(pinit_method.into_iter());
+    additional_methods.extend(sinit_method.into_iter());
+    additional_methods.extend(linit_method.into_iter());
+    additional_methods.extend(cinit_methods.into_iter());
+    additional_methods.extend(ctx_constants_method.into_iter());
+
+    let mut methods = emit_method::from_asts(alloc, emitter, ast_class, &ast_class.methods)?;
+    methods.extend(additional_methods.into_iter());
     let upper_bounds = emit_body::emit_generics_upper_bounds(alloc, &ast_class.tparams, &[], false);
 
     if !no_xhp_attributes {
@@ -926,16 +1354,20 @@ pub fn emit_class<'a, 'arena, 'decl>(
     let doc_comment = ast_class.doc_comment.clone();
     let is_xhp = ast_class.is_xhp || ast_class.has_xhp_keyword;
 
-    let mut flags = HhasClassFlags::empty();
-    flags.set(HhasClassFlags::IS_FINAL, is_final);
-    flags.set(HhasClassFlags::IS_SEALED, is_sealed);
-    flags.set(HhasClassFlags::IS_ABSTRACT, is_abstract);
-    flags.set(HhasClassFlags::IS_INTERFACE, is_interface);
-    flags.set(HhasClassFlags::IS_TRAIT, is_trait);
-    flags.set(HhasClassFlags::IS_XHP, is_xhp);
-    flags.set(HhasClassFlags::IS_CONST, is_const);
-    flags.set(HhasClassFlags::NO_DYNAMIC_PROPS, no_dynamic_props);
-    flags.set(HhasClassFlags::NEEDS_NO_REIFIEDINIT, needs_no_reifiedinit);
+    // A single `Attr` bitset, shared with the HHVM C++ runtime via
+    // `hhvm_types_ffi`, replaces the bespoke `HhasClassFlags` so the emitter
+    // and the runtime read identical flag values from one source of truth.
+    let mut attrs = Attr::empty();
+    attrs.set(Attr::AttrFinal, is_final);
+    attrs.set(Attr::AttrSealed, is_sealed);
+    attrs.set(Attr::AttrAbstract, is_abstract);
+    attrs.set(Attr::AttrInterface, is_interface);
+    attrs.set(Attr::AttrTrait, is_trait);
+    attrs.set(Attr::AttrIsXHP, is_xhp);
+    attrs.set(Attr::AttrIsConst, is_const);
+    attrs.set(Attr::AttrForbidDynamicProps, no_dynamic_props);
+    attrs.set(Attr::AttrNoReifiedInit, needs_no_reifiedinit);
+    attrs.set(Attr::AttrInternal, is_internal);
 
     add_symbol_refs(
         alloc,
@@ -952,7 +1384,8 @@ pub fn emit_class<'a, 'arena, 'decl>(
         enum_includes: Slice::fill_iter(alloc, enum_includes.into_iter()),
         name,
         span,
-        flags,
+        attrs,
+        module_name: Maybe::from(module_name.map(|m| Str::new_str(alloc, m))),
         doc_comment: Maybe::from(doc_comment.map(|c| Str::new_str(alloc, &(c.0).1))),
         uses: Slice::fill_iter(alloc, uses.into_iter().map(|s| Str::new_str(alloc, s))),
         use_aliases,
@@ -0,0 +1,56 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the "hack" directory of this source tree.
+
+use oxidized::pos::Pos;
+
+/// Which kind of fatal a `Fatal` is reported as: a parse fatal or a runtime
+/// fatal. Both kinds are about Hack source the user wrote and carry the `Pos`
+/// in it that the fatal points at.
+#[derive(Debug, Clone)]
+pub enum FatalKind {
+    Parse,
+    Runtime,
+}
+
+/// A structured compiler error.
+///
+/// `Fatal` conflates nothing: it is always a legal-but-rejected (or
+/// otherwise user-visible) Hack program, carrying the `Pos` the fatal HHAS
+/// unit should point at. `Unrecoverable` means the emitter hit a state that
+/// should never happen for any Hack program and is a compiler bug, not a
+/// fatal to report to the user.
+#[derive(Debug, Clone)]
+pub enum Error {
+    Fatal {
+        pos: Pos,
+        kind: FatalKind,
+        msg: std::string::String,
+    },
+    Unrecoverable(std::string::String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn fatal_parse(pos: &Pos, msg: impl Into<std::string::String>) -> Self {
+        Error::Fatal {
+            pos: pos.clone(),
+            kind: FatalKind::Parse,
+            msg: msg.into(),
+        }
+    }
+
+    pub fn fatal_runtime(pos: &Pos, msg: impl Into<std::string::String>) -> Self {
+        Error::Fatal {
+            pos: pos.clone(),
+            kind: FatalKind::Runtime,
+            msg: msg.into(),
+        }
+    }
+
+    pub fn unrecoverable(msg: impl Into<std::string::String>) -> Self {
+        Error::Unrecoverable(msg.into())
+    }
+}